@@ -4,7 +4,7 @@
 FEATURES_WITH_STD=""
 
 # So this is the var to use for all tests.
-FEATURES_WITHOUT_STD="simple_http minreq_http simple_tcp simple_uds proxy"
+FEATURES_WITHOUT_STD="simple_http minreq_http simple_tcp simple_uds simple_stdio proxy bind_addr gzip log test-utils lenient_rpc_errors"
 
 # Run these examples.
 EXAMPLES=""
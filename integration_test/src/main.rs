@@ -146,7 +146,7 @@ fn test_get_network_info(cl: &Client) {
         jsonrpc: Some("2.0"),
     };
 
-    let _ = cl.send_request(request).unwrap();
+    let _ = cl.send_checked(request).unwrap();
 }
 
 fn test_get_block_hash_list(cl: &Client) {
@@ -160,7 +160,7 @@ fn test_get_block_hash_list(cl: &Client) {
         jsonrpc: Some("2.0"),
     };
 
-    let resp = cl.send_request(request).unwrap();
+    let resp = cl.send_checked(request).unwrap();
     assert_eq!(
         resp.result.unwrap().to_string(),
         "\"0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206\""
@@ -178,7 +178,7 @@ fn test_get_block_hash_named(cl: &Client) {
         jsonrpc: Some("2.0"),
     };
 
-    let resp = cl.send_request(request).unwrap();
+    let resp = cl.send_checked(request).unwrap();
     assert_eq!(
         resp.result.unwrap().to_string(),
         "\"0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206\""
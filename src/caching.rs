@@ -0,0 +1,224 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! This module implements a [`crate::client::Transport`] which caches the responses of another
+//! transport, to avoid repeated round trips for requests whose answer doesn't change.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::client::Transport;
+use crate::{BatchEntry, Error, Request, Response};
+
+/// A transport which caches successful responses of an inner transport by method and parameters.
+///
+/// This is meant for requests for immutable data, e.g. `getblockhash` for a confirmed height.
+/// Every request is answered from the cache while a fresh entry exists, so only wrap transports
+/// whose methods can safely be cached for the configured time. Responses carrying an RPC error
+/// are never cached, and batches are always forwarded to the inner transport.
+pub struct CachingTransport<T> {
+    /// The transport to forward requests to on a cache miss.
+    inner: T,
+    /// How long a cached response stays valid.
+    ttl: Duration,
+    /// The maximum number of cached responses.
+    capacity: usize,
+    cache: Mutex<Cache>,
+}
+
+/// The cached responses, keyed by the serialized method and parameters of their request.
+#[derive(Default)]
+struct Cache {
+    entries: HashMap<String, Entry>,
+    /// Incremented on every use of the cache, to find the least recently used entry.
+    clock: u64,
+}
+
+struct Entry {
+    response: Response,
+    /// When the entry was inserted.
+    inserted: Instant,
+    /// The value of [`Cache::clock`] when the entry was last used.
+    last_used: u64,
+}
+
+impl<T: Transport> CachingTransport<T> {
+    /// Creates a new [`CachingTransport`] caching up to `capacity` responses for `ttl` each.
+    ///
+    /// When the cache is full, the least recently used response is evicted.
+    pub fn new(inner: T, ttl: Duration, capacity: usize) -> CachingTransport<T> {
+        CachingTransport { inner, ttl, capacity, cache: Mutex::new(Cache::default()) }
+    }
+
+    /// Returns the inner transport.
+    pub fn into_inner(self) -> T { self.inner }
+
+    /// Drops all cached responses.
+    pub fn clear(&self) {
+        // No part of this codebase should panic, so unwrapping a mutex lock is fine
+        self.cache.lock().expect("poisoned mutex").entries.clear();
+    }
+}
+
+impl Cache {
+    /// Returns the cached response for `key`, if there is one younger than `ttl`.
+    fn get(&mut self, key: &str, ttl: Duration) -> Option<Response> {
+        self.clock += 1;
+        let clock = self.clock;
+        match self.entries.get_mut(key) {
+            Some(entry) if entry.inserted.elapsed() < ttl => {
+                entry.last_used = clock;
+                Some(entry.response.clone())
+            }
+            Some(_) => {
+                self.entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Caches `response` for `key`, evicting entries as needed to stay within `capacity`.
+    fn insert(&mut self, key: String, response: Response, ttl: Duration, capacity: usize) {
+        if capacity == 0 {
+            return;
+        }
+        if !self.entries.contains_key(&key) && self.entries.len() >= capacity {
+            self.entries.retain(|_, entry| entry.inserted.elapsed() < ttl);
+            if self.entries.len() >= capacity {
+                let lru = self
+                    .entries
+                    .iter()
+                    .min_by_key(|(_, entry)| entry.last_used)
+                    .map(|(key, _)| key.clone());
+                if let Some(lru) = lru {
+                    self.entries.remove(&lru);
+                }
+            }
+        }
+        self.clock += 1;
+        let entry = Entry { response, inserted: Instant::now(), last_used: self.clock };
+        self.entries.insert(key, entry);
+    }
+}
+
+impl<T: Transport> Transport for CachingTransport<T> {
+    fn send_request(&self, req: Request) -> Result<Response, Error> {
+        let key = serde_json::to_string(&(req.method, req.params))?;
+        // No part of this codebase should panic, so unwrapping a mutex lock is fine
+        if let Some(mut response) = self.cache.lock().expect("poisoned mutex").get(&key, self.ttl) {
+            // Answer with the ID of this request, so that the client accepts the response.
+            response.id = req.id;
+            return Ok(response);
+        }
+
+        let response = self.inner.send_request(req)?;
+        if response.error.is_none() {
+            self.cache.lock().expect("poisoned mutex").insert(
+                key,
+                response.clone(),
+                self.ttl,
+                self.capacity,
+            );
+        }
+        Ok(response)
+    }
+
+    fn send_batch(&self, reqs: &[Request]) -> Result<Vec<Response>, Error> {
+        self.inner.send_batch(reqs)
+    }
+
+    fn send_mixed_batch(&self, entries: &[BatchEntry]) -> Result<Vec<Response>, Error> {
+        self.inner.send_mixed_batch(entries)
+    }
+
+    fn fmt_target(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.inner.fmt_target(f)?;
+        write!(f, " (cached)")
+    }
+
+    fn supports_batch(&self) -> bool { self.inner.supports_batch() }
+
+    fn check_connection(&self) -> Result<(), Error> { self.inner.check_connection() }
+
+    fn shutdown(&self) -> Result<(), Error> { self.inner.shutdown() }
+}
+
+impl<T: fmt::Debug> fmt::Debug for CachingTransport<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CachingTransport")
+            .field("inner", &self.inner)
+            .field("ttl", &self.ttl)
+            .field("capacity", &self.capacity)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::error::RpcError;
+    use crate::testing::{response, FnTransport};
+    use crate::{arg, Client};
+
+    /// A transport which counts its requests and answers with the count, or with an RPC error for
+    /// the method `fail`.
+    fn counting_transport(count: Arc<AtomicUsize>) -> FnTransport {
+        FnTransport::new(move |req| {
+            let n = count.fetch_add(1, Ordering::SeqCst) + 1;
+            if req.method == "fail" {
+                let error = RpcError { code: -1, message: "nope".into(), data: None };
+                return Ok(Response {
+                    result: None,
+                    error: Some(error),
+                    ..response(req.id, "null")
+                });
+            }
+            Ok(response(req.id, &n.to_string()))
+        })
+    }
+
+    #[test]
+    fn caching() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let tp = counting_transport(count.clone());
+        let client = Client::with_transport(CachingTransport::new(tp, Duration::from_secs(60), 2));
+
+        let params = arg([1]);
+        let other = arg([2]);
+        assert_eq!(client.call::<usize>("getblockhash", Some(&params)).unwrap(), 1);
+        // Answered from the cache, with the ID of the new request.
+        assert_eq!(client.call::<usize>("getblockhash", Some(&params)).unwrap(), 1);
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+
+        // Other parameters are another entry.
+        assert_eq!(client.call::<usize>("getblockhash", Some(&other)).unwrap(), 2);
+        // A third entry evicts the least recently used one.
+        assert_eq!(client.call::<usize>("getblockhash", Some(&params)).unwrap(), 1);
+        assert_eq!(client.call::<usize>("getblockcount", None).unwrap(), 3);
+        assert_eq!(client.call::<usize>("getblockhash", Some(&params)).unwrap(), 1);
+        assert_eq!(client.call::<usize>("getblockhash", Some(&other)).unwrap(), 4);
+
+        // Errors are not cached.
+        assert!(client.call::<usize>("fail", None).is_err());
+        assert!(client.call::<usize>("fail", None).is_err());
+        assert_eq!(count.load(Ordering::SeqCst), 6);
+    }
+
+    #[test]
+    fn ttl() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let tp = counting_transport(count.clone());
+        let tp = CachingTransport::new(tp, Duration::from_millis(50), 10);
+        let client = Client::with_transport(tp);
+
+        assert_eq!(client.call::<usize>("getblockcount", None).unwrap(), 1);
+        assert_eq!(client.call::<usize>("getblockcount", None).unwrap(), 1);
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(client.call::<usize>("getblockcount", None).unwrap(), 2);
+    }
+}
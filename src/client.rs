@@ -9,22 +9,77 @@ use std::borrow::Cow;
 use std::collections::HashMap;
 use std::fmt;
 use std::hash::{Hash, Hasher};
-use std::sync::atomic;
+use std::sync::{atomic, Condvar, Mutex};
 
 use serde_json::value::RawValue;
 use serde_json::Value;
 
-use crate::error::Error;
-use crate::{Request, Response};
+use crate::error::{Error, RpcError};
+use crate::{BatchEntry, Request, Response};
 
 /// An interface for a transport over which to use the JSONRPC protocol.
 pub trait Transport: Send + Sync + 'static {
     /// Sends an RPC request over the transport.
+    ///
+    /// A response carrying an RPC error is returned as `Ok`, since the transport succeeded; see
+    /// [`Response::into_result`] for checking the response itself.
     fn send_request(&self, _: Request) -> Result<Response, Error>;
     /// Sends a batch of RPC requests over the transport.
     fn send_batch(&self, _: &[Request]) -> Result<Vec<Response>, Error>;
+    /// Sends a batch of RPC requests and notifications over the transport.
+    ///
+    /// The default implementation fails with a transport error, since notifications can't be
+    /// sent through [`Transport::send_batch`].
+    fn send_mixed_batch(&self, _: &[BatchEntry]) -> Result<Vec<Response>, Error> {
+        Err(Error::Transport("transport doesn't support notifications in batches".into()))
+    }
     /// Formats the target of this transport. I.e. the URL/socket/...
     fn fmt_target(&self, f: &mut fmt::Formatter) -> fmt::Result;
+    /// Whether the transport can send a batch of requests in one go.
+    ///
+    /// If this returns `false`, [`Client::send_batch`] sends the requests of a batch one by one
+    /// instead of calling [`Transport::send_batch`]. The default implementation returns `true`.
+    fn supports_batch(&self) -> bool { true }
+    /// Checks that the server is reachable, without sending an RPC request.
+    ///
+    /// The default implementation does nothing and always succeeds.
+    fn check_connection(&self) -> Result<(), Error> { Ok(()) }
+
+    /// Releases the resources held by the transport, such as cached connections.
+    ///
+    /// The transport can still be used afterwards, and acquires them again as needed. The
+    /// default implementation does nothing and always succeeds.
+    fn shutdown(&self) -> Result<(), Error> { Ok(()) }
+}
+
+/// A function converting RPC errors into custom error types, see [`Client::error_mapper`].
+pub type ErrorMapper =
+    Box<dyn Fn(&RpcError) -> Option<Box<dyn std::error::Error + Send + Sync>> + Send + Sync>;
+
+/// A function observing RPC errors, see [`Client::rpc_error_hook`].
+pub type RpcErrorHook = Box<dyn Fn(&RpcError) + Send + Sync>;
+
+/// A function rewriting raw results, see [`Client::result_transform`].
+pub type ResultTransform = Box<dyn Fn(Box<RawValue>) -> Box<RawValue> + Send + Sync>;
+
+/// How [`Client::build_request`] sends requests without parameters, see
+/// [`Client::empty_params_mode`].
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum EmptyParams {
+    /// Send `"params": null`.
+    #[default]
+    Null,
+    /// Omit the `params` field, as the JSON-RPC specification allows. Requests with an empty
+    /// array of parameters are sent without them as well.
+    Omit,
+    /// Send an empty array, `"params": []`.
+    EmptyArray,
+}
+
+/// Returns a JSON literal as a [`RawValue`].
+fn static_raw(json: &'static str) -> &'static RawValue {
+    // This is only called with valid JSON literals, so it can't fail.
+    serde_json::from_str(json).expect("valid JSON literal")
 }
 
 /// A JSON-RPC client.
@@ -34,12 +89,135 @@ pub trait Transport: Send + Sync + 'static {
 pub struct Client {
     pub(crate) transport: Box<dyn Transport>,
     nonce: atomic::AtomicUsize,
+    verbose_errors: bool,
+    dedupe_batches: bool,
+    batch_always_array: bool,
+    error_mapper: Option<ErrorMapper>,
+    rpc_error_hook: Option<RpcErrorHook>,
+    result_transform: Option<ResultTransform>,
+    concurrency_limit: Option<Semaphore>,
+    accepted_versions: Vec<String>,
+    empty_params: EmptyParams,
 }
 
 impl Client {
     /// Creates a new client with the given transport.
     pub fn with_transport<T: Transport>(transport: T) -> Client {
-        Client { transport: Box::new(transport), nonce: atomic::AtomicUsize::new(1) }
+        Client {
+            transport: Box::new(transport),
+            nonce: atomic::AtomicUsize::new(1),
+            verbose_errors: false,
+            dedupe_batches: false,
+            batch_always_array: true,
+            error_mapper: None,
+            rpc_error_hook: None,
+            result_transform: None,
+            concurrency_limit: None,
+            accepted_versions: vec![String::from("2.0")],
+            empty_params: EmptyParams::default(),
+        }
+    }
+
+    /// Creates a new client with the given transport, failing requests and batches which take
+    /// longer than `timeout` with [`Error::Timeout`].
+    ///
+    /// This works with any transport, but a request which times out keeps running in the
+    /// background until the transport returns. See [`crate::timeout::TimeoutTransport`].
+    pub fn with_timeout<T: Transport>(transport: T, timeout: std::time::Duration) -> Client {
+        Client::with_transport(crate::timeout::TimeoutTransport::new(transport, timeout))
+    }
+
+    /// Creates a new client with the given transport, which has at most `max` requests in flight
+    /// at any time.
+    ///
+    /// Requests and batches sent while `max` others are in flight block until one of those
+    /// completes. This allows matching the number of threads the server handles requests with.
+    /// A limit of zero is treated as one.
+    pub fn with_max_concurrency<T: Transport>(transport: T, max: usize) -> Client {
+        Client {
+            concurrency_limit: Some(Semaphore::new(max.max(1))),
+            ..Client::with_transport(transport)
+        }
+    }
+
+    /// Sets whether transport errors should be annotated with the request that failed.
+    ///
+    /// If set, transport errors returned by [`Client::send_request`] and [`Client::call`] are
+    /// wrapped in [`Error::RequestFailed`], which carries the method and ID of the request. This
+    /// costs an allocation per request, so it is off by default.
+    pub fn verbose_errors(mut self, verbose: bool) -> Client {
+        self.verbose_errors = verbose;
+        self
+    }
+
+    /// Sets whether identical requests in a batch should be sent only once.
+    ///
+    /// If set, requests in a batch passed to [`Client::send_batch`] which have the same method
+    /// and the same serialized params are sent as a single request, and its response is returned
+    /// for all of them, with the ID of each request. This is only correct for requests without
+    /// side effects, so it is off by default.
+    pub fn dedupe_batches(mut self, dedupe: bool) -> Client {
+        self.dedupe_batches = dedupe;
+        self
+    }
+
+    /// Sets whether a batch of a single request is sent as an array, which is the default.
+    ///
+    /// If unset, [`Client::send_batch`] sends a batch of one request as a bare request object
+    /// instead, for servers which reject or mishandle single-element batches.
+    pub fn batch_always_array(mut self, always_array: bool) -> Client {
+        self.batch_always_array = always_array;
+        self
+    }
+
+    /// Sets the values of the `jsonrpc` field accepted in responses, `"2.0"` by default.
+    ///
+    /// Responses without a `jsonrpc` field are always accepted, responses with any other value
+    /// are rejected with [`Error::VersionMismatch`]. This allows talking to servers which
+    /// e.g. respond with `"1.0"`.
+    pub fn accept_jsonrpc_versions(mut self, versions: &[&str]) -> Client {
+        self.accepted_versions = versions.iter().map(|v| String::from(*v)).collect();
+        self
+    }
+
+    /// Sets how requests without parameters are sent, `"params": null` by default.
+    ///
+    /// Some servers reject `null` or empty parameters for methods which take none, and require
+    /// the field to be omitted, or the other way around. This applies to requests built with
+    /// [`Client::build_request`], and so to [`Client::call`] and its variants. Requests built by
+    /// hand are sent as they are, without the `params` field if it is [`None`].
+    pub fn empty_params_mode(mut self, mode: EmptyParams) -> Client {
+        self.empty_params = mode;
+        self
+    }
+
+    /// Sets a function converting RPC errors into custom error types.
+    ///
+    /// When a response to [`Client::call`] carries an error, it is passed to the mapper first. If
+    /// the mapper returns an error, it is returned as [`Error::MappedRpc`] instead of the usual
+    /// [`Error::Rpc`]. This allows translating error codes into typed errors in one place.
+    pub fn error_mapper(mut self, mapper: ErrorMapper) -> Client {
+        self.error_mapper = Some(mapper);
+        self
+    }
+
+    /// Sets a function called with every RPC error returned by [`Client::call`] and its variants,
+    /// e.g. for logging them in one place.
+    ///
+    /// The hook sees the error before it is passed to the error mapper, if one is set.
+    pub fn rpc_error_hook(mut self, hook: RpcErrorHook) -> Client {
+        self.rpc_error_hook = Some(hook);
+        self
+    }
+
+    /// Sets a function rewriting the raw results of [`Client::call`] and its variants before they
+    /// are deserialized.
+    ///
+    /// This allows working around servers which return results of a slightly wrong shape, e.g.
+    /// a number as a string, in one place. Responses without a result are not passed to it.
+    pub fn result_transform(mut self, transform: ResultTransform) -> Client {
+        self.result_transform = Some(transform);
+        self
     }
 
     /// Builds a request.
@@ -48,14 +226,41 @@ impl Client {
     /// [`crate::arg`] or [`crate::try_arg`].
     pub fn build_request<'a>(&self, method: &'a str, params: Option<&'a RawValue>) -> Request<'a> {
         let nonce = self.nonce.fetch_add(1, atomic::Ordering::Relaxed);
+        let params = match (self.empty_params, params) {
+            (EmptyParams::Null, None) => Some(static_raw("null")),
+            (EmptyParams::Omit, Some(p)) if is_empty_array(p) => None,
+            (EmptyParams::EmptyArray, None) => Some(static_raw("[]")),
+            (_, params) => params,
+        };
         Request { method, params, id: serde_json::Value::from(nonce), jsonrpc: Some("2.0") }
     }
 
     /// Sends a request to a client.
     pub fn send_request(&self, request: Request) -> Result<Response, Error> {
-        self.transport.send_request(request)
+        let _permit = self.concurrency_limit.as_ref().map(Semaphore::acquire);
+        if !self.verbose_errors {
+            return self.transport.send_request(request);
+        }
+
+        let method = request.method.to_owned();
+        let id = request.id.clone();
+        self.transport.send_request(request).map_err(|e| match e {
+            Error::Transport(_) => Error::RequestFailed { method, id, source: Box::new(e) },
+            e => e,
+        })
     }
 
+    /// Checks that the server is reachable, without making an RPC call.
+    ///
+    /// This is a lightweight health check, e.g. for gating startup on the server being up.
+    /// What exactly is checked depends on the transport; see [`Transport::check_connection`].
+    pub fn check_connection(&self) -> Result<(), Error> { self.transport.check_connection() }
+
+    /// Releases the resources held by the transport, such as cached connections.
+    ///
+    /// Unlike dropping the client, this reports errors. See [`Transport::shutdown`].
+    pub fn shutdown(&self) -> Result<(), Error> { self.transport.shutdown() }
+
     /// Sends a batch of requests to the client.
     ///
     /// Note that the requests need to have valid IDs, so it is advised to create the requests
@@ -65,22 +270,97 @@ impl Client {
     ///
     /// The return vector holds the response for the request at the corresponding index. If no
     /// response was provided, it's [`None`].
+    ///
+    /// If the transport does not support batching (see [`Transport::supports_batch`]), the
+    /// requests are sent one after the other.
     pub fn send_batch(&self, requests: &[Request]) -> Result<Vec<Option<Response>>, Error> {
         if requests.is_empty() {
             return Err(Error::EmptyBatch);
         }
+        let _permit = self.concurrency_limit.as_ref().map(Semaphore::acquire);
+        if !self.dedupe_batches {
+            return self.send_unique_batch(requests);
+        }
 
+        // Send each distinct request once, remembering which one each request maps to.
+        let mut unique = Vec::with_capacity(requests.len());
+        let mut seen = HashMap::with_capacity(requests.len());
+        let indices: Vec<usize> = requests
+            .iter()
+            .map(|r| {
+                *seen.entry((r.method, r.params.map(RawValue::get))).or_insert_with(|| {
+                    unique.push(r.clone());
+                    unique.len() - 1
+                })
+            })
+            .collect();
+        let responses = self.send_unique_batch(&unique)?;
+
+        let results = requests
+            .iter()
+            .zip(indices)
+            .map(|(r, i)| responses[i].clone().map(|resp| Response { id: r.id.clone(), ..resp }))
+            .collect();
+        Ok(results)
+    }
+
+    /// Sends a batch mixing requests and notifications.
+    ///
+    /// The server only responds to the requests, so the return vector holds [`None`] for each
+    /// notification, and otherwise the response for the request at the corresponding index, as
+    /// with [`Client::send_batch`]. Since the server doesn't respond to a batch made only of
+    /// notifications, it is rejected with [`Error::EmptyBatch`].
+    ///
+    /// The batch is always sent in one go, so the transport must support it; see
+    /// [`Transport::send_mixed_batch`].
+    pub fn send_mixed_batch(&self, entries: &[BatchEntry]) -> Result<Vec<Option<Response>>, Error> {
+        if !entries.iter().any(|e| matches!(e, BatchEntry::Request(_))) {
+            return Err(Error::EmptyBatch);
+        }
+        let _permit = self.concurrency_limit.as_ref().map(Semaphore::acquire);
+        let responses = self.transport.send_mixed_batch(entries)?;
+        let ids = entries.iter().map(|e| match *e {
+            BatchEntry::Request(ref r) => Some(&r.id),
+            BatchEntry::Notification(_) => None,
+        });
+        self.match_batch_responses(ids, responses)
+    }
+
+    /// Sends a batch of requests, which is known not to be empty.
+    fn send_unique_batch(&self, requests: &[Request]) -> Result<Vec<Option<Response>>, Error> {
         // If the request body is invalid JSON, the response is a single response object.
         // We ignore this case since we are confident we are producing valid JSON.
-        let responses = self.transport.send_batch(requests)?;
-        if responses.len() > requests.len() {
+        let as_array = self.batch_always_array || requests.len() > 1;
+        let responses = if self.transport.supports_batch() && as_array {
+            self.transport.send_batch(requests)?
+        } else {
+            requests
+                .iter()
+                .map(|r| self.transport.send_request(r.clone()))
+                .collect::<Result<Vec<_>, _>>()?
+        };
+        self.match_batch_responses(requests.iter().map(|r| Some(&r.id)), responses)
+    }
+
+    /// Matches the responses to a batch to its entries, given by their ID or [`None`] for a
+    /// notification, which gets no response.
+    fn match_batch_responses<'a>(
+        &self,
+        ids: impl Iterator<Item = Option<&'a Value>>,
+        responses: Vec<Response>,
+    ) -> Result<Vec<Option<Response>>, Error> {
+        let ids: Vec<_> = ids.collect();
+        if responses.len() > ids.iter().flatten().count() {
             return Err(Error::WrongBatchResponseSize);
         }
+        for resp in &responses {
+            self.check_version(resp)?;
+        }
 
         //TODO(stevenroose) check if the server preserved order to avoid doing the mapping
 
         // First index responses by ID and catch duplicate IDs.
-        let mut by_id = HashMap::with_capacity(requests.len());
+        let mut by_id = HashMap::with_capacity(responses.len());
         for resp in responses.into_iter() {
             let id = HashableValue(Cow::Owned(resp.id.clone()));
             if let Some(dup) = by_id.insert(id, resp) {
@@ -88,8 +368,10 @@ impl Client {
             }
         }
         // Match responses to the requests.
-        let results =
-            requests.iter().map(|r| by_id.remove(&HashableValue(Cow::Borrowed(&r.id)))).collect();
+        let results = ids
+            .into_iter()
+            .map(|id| id.and_then(|id| by_id.remove(&HashableValue(Cow::Borrowed(id)))))
+            .collect();
 
         // Since we're also just producing the first duplicate ID, we can also just produce the
         // first incorrect ID in case there are multiple.
@@ -113,14 +395,125 @@ impl Client {
         let id = request.id.clone();
 
         let response = self.send_request(request)?;
-        if response.jsonrpc.is_some() && response.jsonrpc != Some(From::from("2.0")) {
-            return Err(Error::VersionMismatch);
-        }
+        self.transform_result(response)
+            .into_result_with_versions(&id, &self.accepted_versions)
+            .map_err(|e| self.map_error(e))
+    }
+
+    /// Makes a request and returns the result as a generic JSON value.
+    ///
+    /// This is the same as `call::<serde_json::Value>`, for when the shape of the result doesn't
+    /// matter or isn't known in advance, e.g. when exploring an API.
+    pub fn call_value(&self, method: &str, args: Option<&RawValue>) -> Result<Value, Error> {
+        self.call(method, args)
+    }
+
+    /// Makes a request with positional arguments, i.e. passed as an array, and deserializes the
+    /// response.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let client = jsonrpc::Client::simple_http("localhost:8332", None, None).unwrap();
+    /// let hash: String = client.call_positional("getblockhash", &[jsonrpc::arg(0)])?;
+    /// # Ok::<(), jsonrpc::Error>(())
+    /// ```
+    pub fn call_positional<R: for<'a> serde::de::Deserialize<'a>>(
+        &self,
+        method: &str,
+        args: &[Box<RawValue>],
+    ) -> Result<R, Error> {
+        let args = serde_json::value::to_raw_value(&args)?;
+        self.call(method, Some(&args))
+    }
+
+    /// Makes a request with named arguments, i.e. passed as an object, and deserializes the
+    /// response.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # let client = jsonrpc::Client::simple_http("localhost:8332", None, None).unwrap();
+    /// let hash: String = client.call_named("getblockhash", &[("height", jsonrpc::arg(0))])?;
+    /// # Ok::<(), jsonrpc::Error>(())
+    /// ```
+    pub fn call_named<R: for<'a> serde::de::Deserialize<'a>>(
+        &self,
+        method: &str,
+        args: &[(&str, Box<RawValue>)],
+    ) -> Result<R, Error> {
+        let args = serde_json::value::to_raw_value(&NamedArgs(args))?;
+        self.call(method, Some(&args))
+    }
+
+    /// Makes a request and deserializes the response, also returning the result as sent by the
+    /// server.
+    ///
+    /// This is useful to forward or cache the exact result without serializing it again. A
+    /// missing result is returned as `null`.
+    pub fn call_with_raw<R: for<'a> serde::de::Deserialize<'a>>(
+        &self,
+        method: &str,
+        args: Option<&RawValue>,
+    ) -> Result<(R, Box<RawValue>), Error> {
+        let response = self.call_response(method, args)?;
+        let result = response.result().map_err(|e| self.map_error(e))?;
+        let raw = match response.result {
+            Some(raw) => raw,
+            None => serde_json::value::to_raw_value(&Value::Null)?,
+        };
+        Ok((result, raw))
+    }
+
+    /// Makes a request and returns the response, after checking that it matches the request.
+    ///
+    /// Use this with [`Response::borrowed_result`] to deserialize the result into types which
+    /// borrow from the response.
+    pub fn call_response(&self, method: &str, args: Option<&RawValue>) -> Result<Response, Error> {
+        self.send_checked(self.build_request(method, args))
+    }
+
+    /// Sends a request and returns the response, after checking that it matches the request.
+    ///
+    /// Unlike [`Client::send_request`], this fails with [`Error::NonceMismatch`] if the ID of the
+    /// response differs from the one of the request, and with [`Error::VersionMismatch`] if its
+    /// `jsonrpc` field isn't accepted, as [`Client::call`] does. This is for requests built by
+    /// hand, e.g. with a specific ID.
+    pub fn send_checked(&self, request: Request) -> Result<Response, Error> {
+        let id = request.id.clone();
+
+        let response = self.send_request(request)?;
+        self.check_version(&response)?;
         if response.id != id {
             return Err(Error::NonceMismatch);
         }
+        Ok(self.transform_result(response))
+    }
 
-        response.result()
+    /// Passes the result of a response through the result transform, if one is set.
+    fn transform_result(&self, mut response: Response) -> Response {
+        if let Some(ref transform) = self.result_transform {
+            response.result = response.result.map(transform);
+        }
+        response
+    }
+
+    /// Passes RPC errors to the error hook and through the error mapper, if these are set.
+    fn map_error(&self, err: Error) -> Error {
+        if let (Error::Rpc(ref rpc), Some(ref hook)) = (&err, &self.rpc_error_hook) {
+            hook(rpc);
+        }
+        if let (Error::Rpc(ref rpc), Some(ref mapper)) = (&err, &self.error_mapper) {
+            if let Some(mapped) = mapper(rpc) {
+                return Error::MappedRpc(mapped);
+            }
+        }
+        err
+    }
+
+    /// Checks that the `jsonrpc` field of a response, if present, is an accepted version.
+    fn check_version(&self, response: &Response) -> Result<(), Error> {
+        response.check_version(&self.accepted_versions)
     }
 }
 
@@ -136,6 +529,53 @@ impl<T: Transport> From<T> for Client {
     fn from(t: T) -> Client { Client::with_transport(t) }
 }
 
+/// Returns whether `params` is an empty array, whatever its whitespace.
+fn is_empty_array(params: &RawValue) -> bool {
+    params.get().chars().filter(|c| !c.is_whitespace()).eq("[]".chars())
+}
+
+/// Named arguments, serialized as an object with the arguments in the given order.
+struct NamedArgs<'a>(&'a [(&'a str, Box<RawValue>)]);
+
+impl<'a> serde::Serialize for NamedArgs<'a> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.0.iter().map(|(k, v)| (k, v)))
+    }
+}
+
+/// A counting semaphore limiting the number of requests in flight.
+struct Semaphore {
+    available: Mutex<usize>,
+    released: Condvar,
+}
+
+impl Semaphore {
+    fn new(permits: usize) -> Semaphore {
+        Semaphore { available: Mutex::new(permits), released: Condvar::new() }
+    }
+
+    /// Blocks until a permit is available and takes it.
+    fn acquire(&self) -> Permit<'_> {
+        // No part of this codebase should panic, so unwrapping a mutex lock is fine
+        let mut available = self.available.lock().expect("poisoned mutex");
+        while *available == 0 {
+            available = self.released.wait(available).expect("poisoned mutex");
+        }
+        *available -= 1;
+        Permit(self)
+    }
+}
+
+/// A permit taken from a [`Semaphore`], which is given back when dropped.
+struct Permit<'a>(&'a Semaphore);
+
+impl<'a> Drop for Permit<'a> {
+    fn drop(&mut self) {
+        *self.0.available.lock().expect("poisoned mutex") += 1;
+        self.0.released.notify_one();
+    }
+}
+
 /// Newtype around `Value` which allows hashing for use as hashmap keys,
 /// this is needed for batch requests.
 ///
@@ -179,7 +619,11 @@ impl<'a> Hash for HashableValue<'a> {
             Value::Object(ref m) => {
                 "object".hash(state);
                 m.len().hash(state);
-                for (key, val) in m {
+                // Equal objects may iterate in different orders if serde_json preserves the
+                // insertion order, so hash the entries in the order of their keys.
+                let mut entries: Vec<_> = m.iter().collect();
+                entries.sort_by_key(|(key, _)| *key);
+                for (key, val) in entries {
                     key.hash(state);
                     HashableValue(Cow::Borrowed(val)).hash(state);
                 }
@@ -196,6 +640,7 @@ mod tests {
     use std::sync;
 
     use super::*;
+    use crate::testing::{response, FnTransport};
 
     struct DummyTransport;
     impl Transport for DummyTransport {
@@ -215,6 +660,346 @@ mod tests {
         assert!(req1.id != req2.id);
     }
 
+    #[test]
+    fn verbose_errors() {
+        let failing = || FnTransport::new(|_| Err(Error::Transport("connection refused".into())));
+
+        let client = Client::with_transport(failing());
+        match client.call::<()>("test", None) {
+            Err(Error::Transport(_)) => {}
+            res => panic!("expected transport error, got {:?}", res),
+        }
+
+        let client = Client::with_transport(failing()).verbose_errors(true);
+        match client.call::<()>("test", None) {
+            Err(Error::RequestFailed { method, id, source }) => {
+                assert_eq!(method, "test");
+                assert_eq!(id, Value::from(1));
+                assert!(matches!(*source, Error::Transport(_)));
+            }
+            res => panic!("expected request failed error, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn call_with_raw() {
+        let transport = FnTransport::new(|req| Ok(response(req.id, "{ \"a\" : 1 }")));
+
+        let client = Client::with_transport(transport);
+        assert_eq!(client.call_value("test", None).unwrap(), serde_json::json!({ "a": 1 }));
+        let (result, raw) = client.call_with_raw::<Value>("test", None).unwrap();
+        assert_eq!(result, serde_json::json!({ "a": 1 }));
+        // The raw result is returned byte for byte, whitespace included.
+        assert_eq!(raw.get(), "{ \"a\" : 1 }");
+    }
+
+    #[test]
+    fn send_checked() {
+        let transport = FnTransport::new(|req| {
+            Ok(Response {
+                result: None,
+                error: None,
+                id: if req.method == "wrong_id" { Value::from(99) } else { req.id },
+                jsonrpc: Some(String::from(if req.method == "v1" { "1.0" } else { "2.0" })),
+            })
+        });
+
+        let client = Client::with_transport(transport);
+        let request = |method| Request {
+            method,
+            params: None,
+            id: Value::from("custom"),
+            jsonrpc: Some("2.0"),
+        };
+        assert_eq!(client.send_checked(request("test")).unwrap().id, Value::from("custom"));
+        assert!(matches!(client.send_checked(request("wrong_id")), Err(Error::NonceMismatch)));
+        assert!(matches!(client.send_checked(request("v1")), Err(Error::VersionMismatch)));
+        // Sending unchecked returns the response as is.
+        assert_eq!(client.send_request(request("wrong_id")).unwrap().id, Value::from(99));
+    }
+
+    #[test]
+    fn error_mapper() {
+        let transport = FnTransport::new(|req| {
+            let code = if req.method == "getnewaddress" { -5 } else { -1 };
+            Ok(Response {
+                result: None,
+                error: Some(RpcError { code, message: "error".into(), data: None }),
+                id: req.id,
+                jsonrpc: Some(String::from("2.0")),
+            })
+        });
+
+        let client = Client::with_transport(transport).error_mapper(Box::new(|e| match e.code {
+            -5 => Some("invalid address".into()),
+            _ => None,
+        }));
+        match client.call::<()>("getnewaddress", None) {
+            Err(Error::MappedRpc(e)) => assert_eq!(e.to_string(), "invalid address"),
+            res => panic!("expected mapped error, got {:?}", res),
+        }
+        assert!(matches!(client.call::<()>("getinfo", None), Err(Error::Rpc(_))));
+
+        // The hook sees all errors, whether mapped or not.
+        let codes = sync::Arc::new(Mutex::new(vec![]));
+        let hook_codes = codes.clone();
+        let client =
+            client.rpc_error_hook(Box::new(move |e| hook_codes.lock().unwrap().push(e.code)));
+        assert!(client.call::<()>("getnewaddress", None).is_err());
+        assert!(client.call::<()>("getinfo", None).is_err());
+        assert_eq!(*codes.lock().unwrap(), vec![-5, -1]);
+    }
+
+    #[test]
+    fn dedupe_batches() {
+        let sent = sync::Arc::new(atomic::AtomicUsize::new(0));
+        let counter = sent.clone();
+        let transport = FnTransport::new(move |req| {
+            counter.fetch_add(1, atomic::Ordering::SeqCst);
+            Ok(response(req.id, &format!("\"{}\"", req.method)))
+        });
+        let client = Client::with_transport(transport).dedupe_batches(true);
+        let requests = [
+            client.build_request("getblockcount", None),
+            client.build_request("getbestblockhash", None),
+            client.build_request("getblockcount", None),
+        ];
+        let responses = client.send_batch(&requests).unwrap();
+        assert_eq!(sent.load(atomic::Ordering::SeqCst), 2);
+        assert_eq!(responses.len(), 3);
+        for (req, resp) in requests.iter().zip(responses) {
+            let resp = resp.unwrap();
+            assert_eq!(resp.id, req.id);
+            assert_eq!(resp.result::<String>().unwrap(), req.method);
+        }
+    }
+
+    #[test]
+    fn batch_version_mismatch() {
+        let old = || {
+            FnTransport::new(|req| {
+                Ok(Response { jsonrpc: Some(String::from("1.0")), ..response(req.id, "null") })
+            })
+        };
+
+        let client = Client::with_transport(old());
+        let requests = [client.build_request("test", None)];
+        assert!(matches!(client.send_batch(&requests), Err(Error::VersionMismatch)));
+
+        let client = Client::with_transport(old()).accept_jsonrpc_versions(&["1.0", "2.0"]);
+        let requests = [client.build_request("test", None)];
+        assert!(client.send_batch(&requests).is_ok());
+    }
+
+    #[test]
+    fn max_concurrency() {
+        use std::thread;
+        use std::time::Duration;
+
+        let in_flight = atomic::AtomicUsize::new(0);
+        let max_in_flight = sync::Arc::new(atomic::AtomicUsize::new(0));
+        let max = max_in_flight.clone();
+        let transport = FnTransport::new(move |req| {
+            let n = in_flight.fetch_add(1, atomic::Ordering::SeqCst) + 1;
+            max.fetch_max(n, atomic::Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(20));
+            in_flight.fetch_sub(1, atomic::Ordering::SeqCst);
+            Ok(Response { result: None, error: None, id: req.id, jsonrpc: None })
+        });
+        let client = sync::Arc::new(Client::with_max_concurrency(transport, 2));
+        let threads: Vec<_> = (0..6)
+            .map(|_| {
+                let client = client.clone();
+                thread::spawn(move || client.call::<()>("test", None).unwrap())
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert!(max_in_flight.load(atomic::Ordering::SeqCst) <= 2);
+    }
+
+    #[test]
+    fn positional_and_named_args() {
+        let transport = FnTransport::new(|req| Ok(response(req.id, req.params.unwrap().get())));
+
+        let client = Client::with_transport(transport);
+        let params: Box<RawValue> =
+            client.call_positional("getblockhash", &[crate::arg(0), crate::arg(true)]).unwrap();
+        assert_eq!(params.get(), "[0,true]");
+        let params: Box<RawValue> = client
+            .call_named("getblockhash", &[("height", crate::arg(0)), ("verbose", crate::arg(true))])
+            .unwrap();
+        assert_eq!(params.get(), r#"{"height":0,"verbose":true}"#);
+    }
+
+    #[test]
+    fn empty_params_mode() {
+        // Answers with the `params` field it would send, or `"omitted"`.
+        let params_transport = || {
+            FnTransport::new(|req| {
+                let wire: Value = serde_json::to_value(&req).unwrap();
+                let params = wire.get("params").cloned().unwrap_or_else(|| "omitted".into());
+                Ok(response(req.id, &params.to_string()))
+            })
+        };
+
+        let params = |client: &Client, args: &[Box<RawValue>]| -> Value {
+            let none = client.call::<Value>("test", None).unwrap();
+            let empty = client.call_positional::<Value>("test", args).unwrap();
+            Value::Array(vec![none, empty])
+        };
+        let client = Client::with_transport(params_transport());
+        assert_eq!(params(&client, &[]), serde_json::json!([null, []]));
+        let client =
+            Client::with_transport(params_transport()).empty_params_mode(EmptyParams::Omit);
+        assert_eq!(params(&client, &[]), serde_json::json!(["omitted", "omitted"]));
+        assert_eq!(params(&client, &[crate::arg(1)]), serde_json::json!(["omitted", [1]]));
+        let client =
+            Client::with_transport(params_transport()).empty_params_mode(EmptyParams::EmptyArray);
+        assert_eq!(params(&client, &[]), serde_json::json!([[], []]));
+    }
+
+    #[test]
+    fn batch_always_array() {
+        // Answers with the JSON it would send, as a string.
+        let wire_transport = || {
+            let wire = |json: String| Value::String(json).to_string();
+            FnTransport::new(move |req| {
+                Ok(response(req.id.clone(), &wire(serde_json::to_string(&req).unwrap())))
+            })
+            .batch(move |reqs| {
+                Ok(vec![response(reqs[0].id.clone(), &wire(serde_json::to_string(reqs).unwrap()))])
+            })
+        };
+
+        let client = Client::with_transport(wire_transport());
+        let requests = [client.build_request("test", None)];
+        let responses = client.send_batch(&requests).unwrap();
+        let wire = responses[0].as_ref().unwrap().result::<String>().unwrap();
+        assert_eq!(wire, r#"[{"method":"test","params":null,"id":1,"jsonrpc":"2.0"}]"#);
+
+        let client = Client::with_transport(wire_transport()).batch_always_array(false);
+        let requests = [client.build_request("test", None)];
+        let responses = client.send_batch(&requests).unwrap();
+        let wire = responses[0].as_ref().unwrap().result::<String>().unwrap();
+        assert_eq!(wire, r#"{"method":"test","params":null,"id":1,"jsonrpc":"2.0"}"#);
+    }
+
+    #[test]
+    fn batch_without_batch_support() {
+        let transport =
+            FnTransport::new(|req| Ok(response(req.id, &format!("\"{}\"", req.method))))
+                .batch(|_| panic!("batch sent to a transport that doesn't support batches"))
+                .batch_support(false);
+
+        let client = Client::with_transport(transport);
+        let requests = [client.build_request("first", None), client.build_request("second", None)];
+        let responses = client.send_batch(&requests).unwrap();
+        assert_eq!(responses.len(), 2);
+        assert_eq!(responses[0].as_ref().unwrap().result::<String>().unwrap(), "first");
+        assert_eq!(responses[1].as_ref().unwrap().result::<String>().unwrap(), "second");
+    }
+
+    #[test]
+    fn result_transform() {
+        let transport = FnTransport::new(|req| Ok(response(req.id, "\"42\"")));
+
+        let client = Client::with_transport(transport);
+        assert!(matches!(client.call::<u64>("getblockcount", None), Err(Error::Json(_))));
+
+        // Unquote numbers sent as strings.
+        let client = client.result_transform(Box::new(|raw| {
+            let s = raw.get();
+            match s.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+                Some(n) if n.parse::<u64>().is_ok() => RawValue::from_string(n.to_owned()).unwrap(),
+                _ => raw,
+            }
+        }));
+        assert_eq!(client.call::<u64>("getblockcount", None).unwrap(), 42);
+        let (n, raw) = client.call_with_raw::<u64>("getblockcount", None).unwrap();
+        assert_eq!((n, raw.get()), (42, "42"));
+    }
+
+    #[test]
+    fn exotic_ids() {
+        // Answers batches in reverse order, with IDs rebuilt from their JSON.
+        let transport = FnTransport::new(|_| unreachable!()).batch(|reqs| {
+            Ok(reqs
+                .iter()
+                .rev()
+                .map(|r| {
+                    response(serde_json::from_str(&r.id.to_string()).unwrap(), &r.id.to_string())
+                })
+                .collect())
+        });
+
+        let ids = [
+            serde_json::json!("id"),
+            serde_json::json!(-1),
+            serde_json::json!(u64::MAX),
+            serde_json::json!(1.5),
+            serde_json::json!(null),
+            serde_json::json!([1, "two", [3]]),
+            serde_json::json!({ "b": [1], "a": { "c": null } }),
+        ];
+
+        let client = Client::with_transport(transport);
+        let requests: Vec<_> = ids
+            .iter()
+            .map(|id| Request { id: id.clone(), ..client.build_request("test", None) })
+            .collect();
+        let responses = client.send_batch(&requests).unwrap();
+        for (id, response) in ids.iter().zip(responses) {
+            let response = response.unwrap();
+            assert_eq!(response.id, *id);
+            assert_eq!(response.clone().into_result::<Value>(id).unwrap(), *id);
+            assert!(matches!(
+                response.into_result::<Value>(&serde_json::json!("other")),
+                Err(Error::NonceMismatch)
+            ));
+        }
+    }
+
+    #[test]
+    fn mixed_batch() {
+        // Answers the requests of a batch in reverse order, with their ID.
+        let transport = FnTransport::new(|_| unreachable!()).batch(|reqs| {
+            Ok(reqs.iter().rev().map(|r| response(r.id.clone(), &r.id.to_string())).collect())
+        });
+
+        let notification = BatchEntry::Notification(crate::Notification {
+            method: "ping".to_owned(),
+            params: None,
+            jsonrpc: Some("2.0".to_owned()),
+        });
+        let client = Client::with_transport(transport);
+        let entries = [
+            notification.clone(),
+            BatchEntry::Request(client.build_request("a", None)),
+            notification.clone(),
+            BatchEntry::Request(client.build_request("b", None)),
+        ];
+        let responses = client.send_mixed_batch(&entries).unwrap();
+        assert_eq!(responses.len(), 4);
+        assert!(responses[0].is_none());
+        assert_eq!(responses[1].as_ref().unwrap().id, serde_json::json!(1));
+        assert!(responses[2].is_none());
+        assert_eq!(responses[3].as_ref().unwrap().id, serde_json::json!(2));
+
+        // Requests and notifications without parameters are sent in the same shape.
+        let omit = Client::with_transport(FnTransport::new(|_| unreachable!()))
+            .empty_params_mode(EmptyParams::Omit);
+        let entries = [notification.clone(), BatchEntry::Request(omit.build_request("a", None))];
+        assert_eq!(
+            serde_json::to_string(&entries).unwrap(),
+            r#"[{"method":"ping","jsonrpc":"2.0"},{"method":"a","id":1,"jsonrpc":"2.0"}]"#
+        );
+
+        assert!(matches!(client.send_mixed_batch(&[notification]), Err(Error::EmptyBatch)));
+    }
+
     #[test]
     fn hash_value() {
         let val = HashableValue(Cow::Owned(Value::from_str("null").unwrap()));
@@ -32,6 +32,126 @@ pub enum Error {
     BatchDuplicateResponseId(serde_json::Value),
     /// Batch response contained an ID that didn't correspond to any request ID
     WrongBatchResponseId(serde_json::Value),
+    /// A transport error, annotated with the request that was being sent
+    RequestFailed {
+        /// The method of the failed request
+        method: String,
+        /// The ID of the failed request
+        id: serde_json::Value,
+        /// The underlying error
+        source: Box<Error>,
+    },
+    /// An error response, converted by the error mapper of the client
+    MappedRpc(Box<dyn error::Error + Send + Sync>),
+    /// The transport didn't complete the request in time
+    Timeout,
+}
+
+/// Returns whether a transport error may go away if the request is sent again, see
+/// [`Error::is_retryable`].
+// `e` is unused if no transport is enabled.
+#[allow(unused_variables)]
+fn is_retryable_transport_error(e: &(dyn error::Error + Send + Sync + 'static)) -> bool {
+    #[cfg(feature = "simple_http")]
+    if let Some(e) = e.downcast_ref::<crate::simple_http::Error>() {
+        return e.is_retryable();
+    }
+    #[cfg(feature = "minreq_http")]
+    if let Some(e) = e.downcast_ref::<crate::minreq_http::Error>() {
+        return e.is_retryable();
+    }
+    #[cfg(feature = "simple_tcp")]
+    if let Some(e) = e.downcast_ref::<crate::simple_tcp::Error>() {
+        return e.is_retryable();
+    }
+    #[cfg(all(feature = "simple_uds", not(windows)))]
+    if let Some(e) = e.downcast_ref::<crate::simple_uds::Error>() {
+        return e.is_retryable();
+    }
+    #[cfg(feature = "simple_stdio")]
+    if let Some(e) = e.downcast_ref::<crate::simple_stdio::Error>() {
+        return e.is_retryable();
+    }
+    true
+}
+
+impl Error {
+    /// Returns whether the request may succeed if sent again, i.e. whether the error is a
+    /// failure of the connection or a timeout.
+    ///
+    /// Errors of the transports of this crate which won't go away on a retry, e.g. an invalid
+    /// URL, an HTTP error code like 401 or a response exceeding a limit, are not retryable.
+    /// Errors of other transports are assumed to be. Errors answered by the server, like RPC
+    /// errors and malformed or mismatched responses, are not retryable.
+    pub fn is_retryable(&self) -> bool {
+        match *self {
+            Error::Timeout => true,
+            Error::Transport(ref e) => is_retryable_transport_error(&**e),
+            Error::RequestFailed { ref source, .. } => source.is_retryable(),
+            _ => false,
+        }
+    }
+
+    /// Returns whether the error is a transport error, possibly annotated with its request.
+    pub fn is_transport(&self) -> bool {
+        match *self {
+            Error::Transport(_) => true,
+            Error::RequestFailed { ref source, .. } => source.is_transport(),
+            _ => false,
+        }
+    }
+
+    /// Returns whether the error is an error response of the server, possibly converted by the
+    /// error mapper of the client.
+    pub fn is_rpc(&self) -> bool {
+        match *self {
+            Error::Rpc(_) | Error::MappedRpc(_) => true,
+            Error::RequestFailed { ref source, .. } => source.is_rpc(),
+            _ => false,
+        }
+    }
+
+    /// Returns a structured JSON representation of the error, e.g. for API responses or logs.
+    ///
+    /// The result is an object with a `kind` field naming the error and a `message` field
+    /// describing it. RPC errors additionally carry their `code` and, if present, their `data`.
+    /// For [`Error::RequestFailed`] the underlying error is returned, with the `method` and `id`
+    /// of the failed request added.
+    pub fn to_json(&self) -> serde_json::Value {
+        use Error::*;
+
+        let kind = match *self {
+            Transport(_) => "transport",
+            Json(_) => "json",
+            Rpc(ref e) => {
+                let mut obj = serde_json::json!({
+                    "kind": "rpc",
+                    "message": e.message,
+                    "code": e.code,
+                });
+                if let Some(data) = e.data.as_ref().and_then(|d| serde_json::from_str(d.get()).ok())
+                {
+                    obj["data"] = data;
+                }
+                return obj;
+            }
+            NonceMismatch => "nonce_mismatch",
+            VersionMismatch => "version_mismatch",
+            EmptyBatch => "empty_batch",
+            WrongBatchResponseSize => "wrong_batch_response_size",
+            BatchDuplicateResponseId(_) => "batch_duplicate_response_id",
+            WrongBatchResponseId(_) => "wrong_batch_response_id",
+            RequestFailed { ref method, ref id, ref source } => {
+                let mut obj = source.to_json();
+                obj["method"] = method.as_str().into();
+                obj["id"] = id.clone();
+                return obj;
+            }
+            MappedRpc(_) => "mapped_rpc",
+            Timeout => "timeout",
+        };
+        serde_json::json!({ "kind": kind, "message": self.to_string() })
+    }
 }
 
 impl From<serde_json::Error> for Error {
@@ -56,6 +176,10 @@ impl fmt::Display for Error {
             VersionMismatch => write!(f, "`jsonrpc` field set to non-\"2.0\""),
             EmptyBatch => write!(f, "batches can't be empty"),
             WrongBatchResponseSize => write!(f, "too many responses returned in batch"),
+            RequestFailed { ref method, ref id, ref source } =>
+                write!(f, "request `{}` with ID {} failed: {}", method, id, source),
+            MappedRpc(ref e) => write!(f, "RPC error response: {}", e),
+            Timeout => write!(f, "request timed out"),
         }
     }
 }
@@ -71,9 +195,11 @@ impl error::Error for Error {
             | EmptyBatch
             | WrongBatchResponseSize
             | BatchDuplicateResponseId(_)
-            | WrongBatchResponseId(_) => None,
-            Transport(ref e) => Some(&**e),
+            | WrongBatchResponseId(_)
+            | Timeout => None,
+            Transport(ref e) | MappedRpc(ref e) => Some(&**e),
             Json(ref e) => Some(e),
+            RequestFailed { ref source, .. } => Some(&**source),
         }
     }
 }
@@ -100,7 +226,7 @@ impl error::Error for Error {
 /// RIGHTS OR ANY IMPLIED WARRANTIES OF MERCHANTABILITY OR FITNESS FOR A
 /// PARTICULAR PURPOSE.
 ///
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum StandardError {
     /// Invalid JSON was received by the server.
     /// An error occurred on the server while parsing the JSON text.
@@ -115,6 +241,20 @@ pub enum StandardError {
     InternalError,
 }
 
+impl StandardError {
+    /// Returns the standard error with the given code, if there is one.
+    pub(crate) fn from_code(code: i32) -> Option<StandardError> {
+        match code {
+            -32700 => Some(StandardError::ParseError),
+            -32600 => Some(StandardError::InvalidRequest),
+            -32601 => Some(StandardError::MethodNotFound),
+            -32602 => Some(StandardError::InvalidParams),
+            -32603 => Some(StandardError::InternalError),
+            _ => None,
+        }
+    }
+}
+
 /// A JSONRPC error object
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct RpcError {
@@ -126,6 +266,45 @@ pub struct RpcError {
     pub data: Option<Box<serde_json::value::RawValue>>,
 }
 
+/// Deserializes an optional [`RpcError`], additionally accepting a bare string in place of the
+/// error object as sent by some non-conforming servers. The string becomes the error message
+/// and the error code is set to 0.
+#[cfg(feature = "lenient_rpc_errors")]
+pub(crate) fn deserialize_lenient_rpc_error<'de, D>(d: D) -> Result<Option<RpcError>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    use serde::de;
+
+    struct Visitor;
+
+    impl<'de> de::Visitor<'de> for Visitor {
+        type Value = Option<RpcError>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("an error object, a string or null")
+        }
+
+        fn visit_none<E: de::Error>(self) -> Result<Self::Value, E> { Ok(None) }
+
+        fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> { Ok(None) }
+
+        fn visit_some<D: serde::Deserializer<'de>>(self, d: D) -> Result<Self::Value, D::Error> {
+            d.deserialize_any(self)
+        }
+
+        fn visit_str<E: de::Error>(self, message: &str) -> Result<Self::Value, E> {
+            Ok(Some(RpcError { code: 0, message: message.to_owned(), data: None }))
+        }
+
+        fn visit_map<A: de::MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+            RpcError::deserialize(de::value::MapAccessDeserializer::new(map)).map(Some)
+        }
+    }
+
+    d.deserialize_option(Visitor)
+}
+
 /// Create a standard error responses
 pub fn standard_error(
     code: StandardError,
@@ -172,7 +351,62 @@ mod tests {
     use super::StandardError::{
         InternalError, InvalidParams, InvalidRequest, MethodNotFound, ParseError,
     };
-    use super::{result_to_response, standard_error};
+    use super::{result_to_response, standard_error, Error, RpcError};
+
+    #[test]
+    fn error_to_json() {
+        let err = Error::Rpc(standard_error(
+            MethodNotFound,
+            Some(serde_json::value::to_raw_value(&"getinfo").unwrap()),
+        ));
+        assert_eq!(
+            err.to_json(),
+            serde_json::json!({
+                "kind": "rpc",
+                "message": "Method not found",
+                "code": -32601,
+                "data": "getinfo",
+            })
+        );
+
+        let err = Error::RequestFailed {
+            method: "getinfo".into(),
+            id: From::from(1),
+            source: Box::new(Error::Transport("connection refused".into())),
+        };
+        assert_eq!(
+            err.to_json(),
+            serde_json::json!({
+                "kind": "transport",
+                "message": "transport error: connection refused",
+                "method": "getinfo",
+                "id": 1,
+            })
+        );
+
+        assert_eq!(
+            Error::EmptyBatch.to_json(),
+            serde_json::json!({ "kind": "empty_batch", "message": "batches can't be empty" })
+        );
+    }
+
+    #[test]
+    fn as_standard_error() {
+        for &err in &[ParseError, InvalidRequest, MethodNotFound, InvalidParams, InternalError] {
+            let resp = result_to_response(Err(standard_error(err, None)), From::from(1));
+            assert_eq!(resp.as_standard_error(), Some(err));
+        }
+    }
+
+    #[test]
+    fn not_standard_error() {
+        let resp = result_to_response(Ok(serde_json::Value::Null), From::from(1));
+        assert_eq!(resp.as_standard_error(), None);
+
+        let err = RpcError { code: -8, message: "Block height out of range".into(), data: None };
+        let resp = result_to_response(Err(err), From::from(1));
+        assert_eq!(resp.as_standard_error(), None);
+    }
 
     #[test]
     fn test_parse_error() {
@@ -192,6 +426,72 @@ mod tests {
         assert_eq!(resp.error.unwrap().code, -32600);
     }
 
+    #[test]
+    fn classification() {
+        let transport = || Error::Transport("connection refused".into());
+        let rpc = || Error::Rpc(standard_error(InternalError, None));
+        let request_failed = |source| Error::RequestFailed {
+            method: "getinfo".into(),
+            id: serde_json::Value::from(1),
+            source: Box::new(source),
+        };
+
+        // (error, is_retryable, is_transport, is_rpc)
+        #[allow(unused_mut)]
+        let mut cases = vec![
+            (transport(), true, true, false),
+            (request_failed(transport()), true, true, false),
+            (rpc(), false, false, true),
+            (request_failed(rpc()), false, false, true),
+            (Error::MappedRpc("mapped".into()), false, false, true),
+            (Error::Timeout, true, false, false),
+            (request_failed(Error::Timeout), true, false, false),
+            (Error::Json(serde_json::from_str::<u8>("x").unwrap_err()), false, false, false),
+            (Error::NonceMismatch, false, false, false),
+            (Error::VersionMismatch, false, false, false),
+            (Error::EmptyBatch, false, false, false),
+            (Error::WrongBatchResponseSize, false, false, false),
+            (Error::BatchDuplicateResponseId(1.into()), false, false, false),
+            (Error::WrongBatchResponseId(1.into()), false, false, false),
+        ];
+        #[cfg(feature = "simple_http")]
+        {
+            use crate::simple_http::Error as HttpError;
+
+            let http = |e: HttpError| Error::Transport(Box::new(e));
+            let refused = || std::io::Error::from(std::io::ErrorKind::ConnectionRefused);
+            cases.extend(vec![
+                (http(HttpError::SocketError(refused())), true, true, false),
+                (request_failed(http(HttpError::SocketError(refused()))), true, true, false),
+                (http(HttpError::DeadlineExceeded), true, true, false),
+                (http(HttpError::HttpErrorCode(503)), true, true, false),
+                (http(HttpError::InvalidUrl { url: "x".into(), reason: "x" }), false, true, false),
+                (http(HttpError::HttpErrorCode(404)), false, true, false),
+                (http(HttpError::Unauthorized { scheme: None, realm: None }), false, true, false),
+                (http(HttpError::JsonTooDeep { max: 1 }), false, true, false),
+                (http(HttpError::ByteBudgetExceeded { budget: 1 }), false, true, false),
+                (http(HttpError::HttpHeadersTooLarge), false, true, false),
+            ]);
+        }
+        #[cfg(feature = "minreq_http")]
+        {
+            let bearer = Box::new(crate::minreq_http::Error::InvalidBearerToken);
+            cases.push((Error::Transport(bearer), false, true, false));
+        }
+        #[cfg(feature = "simple_tcp")]
+        cases.push((
+            Error::Transport(Box::new(crate::simple_tcp::Error::Timeout)),
+            true,
+            true,
+            false,
+        ));
+        for (err, retryable, transport, rpc) in cases {
+            assert_eq!(err.is_retryable(), retryable, "{}", err);
+            assert_eq!(err.is_transport(), transport, "{}", err);
+            assert_eq!(err.is_rpc(), rpc, "{}", err);
+        }
+    }
+
     #[test]
     fn test_method_not_found() {
         let resp = result_to_response(Err(standard_error(MethodNotFound, None)), From::from(1));
@@ -0,0 +1,122 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! This module implements a [`crate::client::Transport`] which forwards requests to a primary
+//! transport, and falls back to a secondary transport if the primary one fails.
+
+use std::fmt;
+
+use crate::client::Transport;
+use crate::{BatchEntry, Error, Request, Response};
+
+/// A transport which tries a primary transport first and falls back to a secondary one.
+///
+/// Only transport errors ([`Error::Transport`]) trigger the fallback, e.g. when the primary
+/// server can't be reached. Any other error, and in particular a response carrying an RPC error,
+/// is returned as is, since the secondary server would likely answer the same. Timeouts don't
+/// trigger the fallback either: the primary server may still run the request, which must not
+/// run twice if it has side effects.
+#[derive(Debug, Clone)]
+pub struct FallbackTransport<A, B> {
+    /// The transport to try first.
+    pub primary: A,
+    /// The transport to use if the primary one fails.
+    pub secondary: B,
+}
+
+impl<A: Transport, B: Transport> FallbackTransport<A, B> {
+    /// Creates a new [`FallbackTransport`].
+    pub fn new(primary: A, secondary: B) -> FallbackTransport<A, B> {
+        FallbackTransport { primary, secondary }
+    }
+}
+
+impl<A: Transport, B: Transport> Transport for FallbackTransport<A, B> {
+    fn send_request(&self, req: Request) -> Result<Response, Error> {
+        match self.primary.send_request(req.clone()) {
+            Err(Error::Transport(_)) => self.secondary.send_request(req),
+            res => res,
+        }
+    }
+
+    fn send_batch(&self, reqs: &[Request]) -> Result<Vec<Response>, Error> {
+        match self.primary.send_batch(reqs) {
+            Err(Error::Transport(_)) => self.secondary.send_batch(reqs),
+            res => res,
+        }
+    }
+
+    fn send_mixed_batch(&self, entries: &[BatchEntry]) -> Result<Vec<Response>, Error> {
+        match self.primary.send_mixed_batch(entries) {
+            Err(Error::Transport(_)) => self.secondary.send_mixed_batch(entries),
+            res => res,
+        }
+    }
+
+    fn fmt_target(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.primary.fmt_target(f)?;
+        write!(f, " (fallback: ")?;
+        self.secondary.fmt_target(f)?;
+        write!(f, ")")
+    }
+
+    fn supports_batch(&self) -> bool {
+        self.primary.supports_batch() && self.secondary.supports_batch()
+    }
+
+    fn check_connection(&self) -> Result<(), Error> {
+        match self.primary.check_connection() {
+            Err(Error::Transport(_)) => self.secondary.check_connection(),
+            res => res,
+        }
+    }
+
+    fn shutdown(&self) -> Result<(), Error> {
+        let primary = self.primary.shutdown();
+        self.secondary.shutdown()?;
+        primary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::RpcError;
+    use crate::testing::{response, FnTransport};
+    use crate::Client;
+
+    /// A transport which answers with its name, or fails with the given error.
+    fn named_transport(name: &'static str, err: Option<fn() -> Error>) -> FnTransport {
+        FnTransport::new(move |req| match err {
+            Some(err) => Err(err()),
+            None => Ok(response(req.id, &format!("\"{}\"", name))),
+        })
+    }
+
+    #[test]
+    fn fallback() {
+        let tp = FallbackTransport::new(named_transport("a", None), named_transport("b", None));
+        let client = Client::with_transport(tp);
+        assert_eq!(client.call::<String>("test", None).unwrap(), "a");
+        assert_eq!(format!("{:?}", client), "jsonrpc::Client(fn (fallback: fn))");
+
+        let down = || Error::Transport("connection refused".into());
+        let tp =
+            FallbackTransport::new(named_transport("a", Some(down)), named_transport("b", None));
+        let client = Client::with_transport(tp);
+        assert_eq!(client.call::<String>("test", None).unwrap(), "b");
+
+        // The primary server may still run a request which timed out, so it isn't sent again.
+        let timeout = || Error::Timeout;
+        let tp =
+            FallbackTransport::new(named_transport("a", Some(timeout)), named_transport("b", None));
+        let client = Client::with_transport(tp);
+        assert!(matches!(client.call::<String>("test", None), Err(Error::Timeout)));
+
+        // Errors answered by the server are not masked.
+        let rpc = || Error::Rpc(RpcError { code: -1, message: "nope".into(), data: None });
+        let tp =
+            FallbackTransport::new(named_transport("a", Some(rpc)), named_transport("b", None));
+        let client = Client::with_transport(tp);
+        assert!(matches!(client.call::<String>("test", None), Err(Error::Rpc(_))));
+    }
+}
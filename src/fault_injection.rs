@@ -0,0 +1,197 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! This module implements a [`crate::client::Transport`] which injects faults into the requests
+//! sent through another transport, for testing the handling of errors and timeouts.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::{fmt, thread};
+
+use crate::client::Transport;
+use crate::error::RpcError;
+use crate::{BatchEntry, Error, Request, Response};
+
+/// The behavior of a [`FaultInjectionTransport`] for a single request or batch.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Fault {
+    /// Forward the request to the inner transport.
+    Pass,
+    /// Fail with a transport error, without forwarding the request.
+    FailTransport,
+    /// Wait for the given time, then forward the request to the inner transport.
+    Delay(Duration),
+    /// Answer with an RPC error with the given code, without forwarding the request.
+    FailRpc(i32),
+}
+
+/// A transport which applies a programmed sequence of faults to the requests sent through it.
+///
+/// Each request, or batch, takes the next [`Fault`] of the sequence. Once the sequence is used up,
+/// all requests are passed to the inner transport.
+///
+/// # Examples
+///
+/// Testing a retry loop against a server which fails twice:
+///
+/// ```
+/// # use jsonrpc::{Request, Response};
+/// use jsonrpc::fault_injection::{Fault, FaultInjectionTransport};
+/// use jsonrpc::{Client, Error, Transport};
+///
+/// # struct Server;
+/// # impl Transport for Server {
+/// #     fn send_request(&self, req: Request) -> Result<Response, Error> {
+/// #         Ok(Response { result: Some(jsonrpc::arg(42)), error: None, id: req.id, jsonrpc: None })
+/// #     }
+/// #     fn send_batch(&self, _: &[Request]) -> Result<Vec<Response>, Error> { Ok(vec![]) }
+/// #     fn fmt_target(&self, _: &mut std::fmt::Formatter) -> std::fmt::Result { Ok(()) }
+/// # }
+/// let faults = vec![Fault::FailTransport, Fault::FailTransport];
+/// let client = Client::with_transport(FaultInjectionTransport::new(Server, faults));
+///
+/// let mut attempts = 0;
+/// let count = loop {
+///     attempts += 1;
+///     match client.call::<u64>("getblockcount", None) {
+///         Err(e) if e.is_retryable() && attempts < 5 => continue,
+///         res => break res.unwrap(),
+///     }
+/// };
+/// assert_eq!((count, attempts), (42, 3));
+/// ```
+pub struct FaultInjectionTransport<T> {
+    /// The transport to forward requests to.
+    inner: T,
+    /// The faults to apply to the next requests.
+    faults: Mutex<VecDeque<Fault>>,
+}
+
+impl<T: Transport> FaultInjectionTransport<T> {
+    /// Creates a new [`FaultInjectionTransport`] applying `faults` to the next requests in order.
+    pub fn new(inner: T, faults: Vec<Fault>) -> FaultInjectionTransport<T> {
+        FaultInjectionTransport { inner, faults: Mutex::new(faults.into()) }
+    }
+
+    /// Appends faults to the sequence, to be applied after the ones still pending.
+    pub fn push_faults(&self, faults: impl IntoIterator<Item = Fault>) {
+        // No part of this codebase should panic, so unwrapping a mutex lock is fine
+        self.faults.lock().expect("poisoned mutex").extend(faults);
+    }
+
+    /// Returns the inner transport.
+    pub fn into_inner(self) -> T { self.inner }
+
+    /// Takes the fault to apply to the next request.
+    fn next_fault(&self) -> Fault {
+        // No part of this codebase should panic, so unwrapping a mutex lock is fine
+        self.faults.lock().expect("poisoned mutex").pop_front().unwrap_or(Fault::Pass)
+    }
+}
+
+/// Returns an error response to `req` with the given code.
+fn rpc_error(req: &Request, code: i32) -> Response {
+    Response {
+        result: None,
+        error: Some(RpcError { code, message: "injected RPC error".to_owned(), data: None }),
+        id: req.id.clone(),
+        jsonrpc: Some(String::from("2.0")),
+    }
+}
+
+impl<T: Transport> Transport for FaultInjectionTransport<T> {
+    fn send_request(&self, req: Request) -> Result<Response, Error> {
+        match self.next_fault() {
+            Fault::Pass => self.inner.send_request(req),
+            Fault::FailTransport => Err(Error::Transport("injected transport failure".into())),
+            Fault::Delay(delay) => {
+                thread::sleep(delay);
+                self.inner.send_request(req)
+            }
+            Fault::FailRpc(code) => Ok(rpc_error(&req, code)),
+        }
+    }
+
+    fn send_batch(&self, reqs: &[Request]) -> Result<Vec<Response>, Error> {
+        match self.next_fault() {
+            Fault::Pass => self.inner.send_batch(reqs),
+            Fault::FailTransport => Err(Error::Transport("injected transport failure".into())),
+            Fault::Delay(delay) => {
+                thread::sleep(delay);
+                self.inner.send_batch(reqs)
+            }
+            Fault::FailRpc(code) => Ok(reqs.iter().map(|req| rpc_error(req, code)).collect()),
+        }
+    }
+
+    fn send_mixed_batch(&self, entries: &[BatchEntry]) -> Result<Vec<Response>, Error> {
+        match self.next_fault() {
+            Fault::Pass => self.inner.send_mixed_batch(entries),
+            Fault::FailTransport => Err(Error::Transport("injected transport failure".into())),
+            Fault::Delay(delay) => {
+                thread::sleep(delay);
+                self.inner.send_mixed_batch(entries)
+            }
+            Fault::FailRpc(code) => Ok(entries
+                .iter()
+                .filter_map(|entry| match *entry {
+                    BatchEntry::Request(ref req) => Some(rpc_error(req, code)),
+                    BatchEntry::Notification(_) => None,
+                })
+                .collect()),
+        }
+    }
+
+    fn fmt_target(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.inner.fmt_target(f)?;
+        write!(f, " (with faults)")
+    }
+
+    fn supports_batch(&self) -> bool { self.inner.supports_batch() }
+
+    fn check_connection(&self) -> Result<(), Error> { self.inner.check_connection() }
+
+    fn shutdown(&self) -> Result<(), Error> { self.inner.shutdown() }
+}
+
+impl<T: fmt::Debug> fmt::Debug for FaultInjectionTransport<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FaultInjectionTransport")
+            .field("inner", &self.inner)
+            .field("faults", &self.faults)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::testing::{response, FnTransport};
+    use crate::Client;
+
+    #[test]
+    fn faults() {
+        let faults = vec![
+            Fault::Pass,
+            Fault::FailTransport,
+            Fault::Delay(Duration::from_millis(50)),
+            Fault::FailRpc(-28),
+        ];
+        let ok = FnTransport::new(|req| Ok(response(req.id, "true")));
+        let client = Client::with_transport(FaultInjectionTransport::new(ok, faults));
+
+        assert!(client.call::<bool>("test", None).unwrap());
+        assert!(matches!(client.call::<bool>("test", None), Err(Error::Transport(_))));
+        let start = Instant::now();
+        assert!(client.call::<bool>("test", None).unwrap());
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        match client.call::<bool>("test", None) {
+            Err(Error::Rpc(e)) => assert_eq!(e.code, -28),
+            res => panic!("unexpected result {:?}", res),
+        }
+        // The sequence is used up.
+        assert!(client.call::<bool>("test", None).unwrap());
+    }
+}
@@ -11,7 +11,7 @@ use std::time::Duration;
 use std::{error, fmt};
 
 use crate::client::Transport;
-use crate::{Request, Response};
+use crate::{BatchEntry, Request, Response};
 
 const DEFAULT_URL: &str = "http://localhost";
 const DEFAULT_PORT: u16 = 8332; // the default RPC port for bitcoind.
@@ -27,8 +27,10 @@ pub struct MinreqHttpTransport {
     url: String,
     /// timeout only supports second granularity.
     timeout: Duration,
-    /// The value of the `Authorization` HTTP header, i.e., a base64 encoding of 'user:password'.
-    basic_auth: Option<String>,
+    /// The value of the `Authorization` HTTP header, e.g. a base64 encoding of 'user:password'.
+    authorization: Option<String>,
+    /// Whether to send requests as indented JSON.
+    pretty_print_requests: bool,
 }
 
 impl Default for MinreqHttpTransport {
@@ -36,7 +38,8 @@ impl Default for MinreqHttpTransport {
         MinreqHttpTransport {
             url: format!("{}:{}", DEFAULT_URL, DEFAULT_PORT),
             timeout: Duration::from_secs(DEFAULT_TIMEOUT_SECONDS),
-            basic_auth: None,
+            authorization: None,
+            pretty_print_requests: false,
         }
     }
 }
@@ -52,20 +55,24 @@ impl MinreqHttpTransport {
     where
         R: for<'a> serde::de::Deserialize<'a>,
     {
-        let req = match &self.basic_auth {
-            Some(auth) => minreq::Request::new(minreq::Method::Post, &self.url)
-                .with_timeout(self.timeout.as_secs())
-                .with_header("Authorization", auth)
-                .with_json(&req)?,
-            None => minreq::Request::new(minreq::Method::Post, &self.url)
-                .with_timeout(self.timeout.as_secs())
-                .with_json(&req)?,
+        let mut http_req = minreq::Request::new(minreq::Method::Post, &self.url)
+            .with_timeout(self.timeout.as_secs());
+        if let Some(auth) = &self.authorization {
+            http_req = http_req.with_header("Authorization", auth);
+        }
+        let req = if self.pretty_print_requests {
+            http_req
+                .with_header("Content-Type", "application/json; charset=UTF-8")
+                .with_body(serde_json::to_vec_pretty(&req)?)
+        } else {
+            http_req.with_json(&req)?
         };
 
         // Send the request and parse the response. If the response is an error that does not
         // contain valid JSON in its body (for instance if the bitcoind HTTP server work queue
         // depth is exceeded), return the raw HTTP error so users can match against it.
         let resp = req.send()?;
+        debug!("received HTTP {} response of {} bytes", resp.status_code, resp.as_bytes().len());
         match resp.json() {
             Ok(json) => Ok(json),
             Err(minreq_err) =>
@@ -83,13 +90,20 @@ impl MinreqHttpTransport {
 
 impl Transport for MinreqHttpTransport {
     fn send_request(&self, req: Request) -> Result<Response, crate::Error> {
+        debug!("sending request {} with id {}", req.method, req.id);
         Ok(self.request(req)?)
     }
 
     fn send_batch(&self, reqs: &[Request]) -> Result<Vec<Response>, crate::Error> {
+        debug!("sending batch of {} requests", reqs.len());
         Ok(self.request(reqs)?)
     }
 
+    fn send_mixed_batch(&self, entries: &[BatchEntry]) -> Result<Vec<Response>, crate::Error> {
+        debug!("sending batch of {} requests and notifications", entries.len());
+        Ok(self.request(entries)?)
+    }
+
     fn fmt_target(&self, f: &mut fmt::Formatter) -> fmt::Result { write!(f, "{}", self.url) }
 }
 
@@ -116,6 +130,14 @@ impl Builder {
         Ok(self)
     }
 
+    /// Sets whether to send requests as indented JSON, which is easier to read in traffic dumps.
+    ///
+    /// Servers accept either form, so this is purely a debugging aid. Off by default.
+    pub fn pretty_print_requests(mut self, pretty: bool) -> Self {
+        self.tp.pretty_print_requests = pretty;
+        self
+    }
+
     /// Adds authentication information to the transport.
     pub fn basic_auth(mut self, user: String, pass: Option<String>) -> Self {
         let mut s = user;
@@ -123,7 +145,7 @@ impl Builder {
         if let Some(ref pass) = pass {
             s.push_str(pass.as_ref());
         }
-        self.tp.basic_auth = Some(format!("Basic {}", &base64::encode(s.as_bytes())));
+        self.tp.authorization = Some(format!("Basic {}", &base64::encode(s.as_bytes())));
         self
     }
 
@@ -144,10 +166,24 @@ impl Builder {
     /// let client = MinreqHttpTransport::builder().cookie_auth(cookie);
     /// ```
     pub fn cookie_auth<S: AsRef<str>>(mut self, cookie: S) -> Self {
-        self.tp.basic_auth = Some(format!("Basic {}", &base64::encode(cookie.as_ref().as_bytes())));
+        self.tp.authorization =
+            Some(format!("Basic {}", &base64::encode(cookie.as_ref().as_bytes())));
         self
     }
 
+    /// Authenticates with a bearer token, as used by API gateways, instead of user and password.
+    ///
+    /// Fails with [`Error::InvalidBearerToken`] if the token contains a line break, which would
+    /// end the header.
+    pub fn bearer_auth<S: Into<String>>(mut self, token: S) -> Result<Self, Error> {
+        let token = token.into();
+        if token.contains(['\r', '\n']) {
+            return Err(Error::InvalidBearerToken);
+        }
+        self.tp.authorization = Some(format!("Bearer {}", token));
+        Ok(self)
+    }
+
     /// Builds the final [`MinreqHttpTransport`].
     pub fn build(self) -> MinreqHttpTransport { self.tp }
 }
@@ -185,6 +221,19 @@ pub enum Error {
     Minreq(minreq::Error),
     /// HTTP error that does not contain valid JSON as body.
     Http(HttpError),
+    /// The bearer token passed to [`Builder::bearer_auth`] contains a line break.
+    InvalidBearerToken,
+}
+
+impl Error {
+    /// Returns whether the request may succeed if sent again, see [`crate::Error::is_retryable`].
+    pub(crate) fn is_retryable(&self) -> bool {
+        match *self {
+            Error::Minreq(minreq::Error::IoError(_)) => true,
+            Error::Http(ref e) => matches!(e.status_code, 502..=504),
+            _ => false,
+        }
+    }
 }
 
 impl fmt::Display for Error {
@@ -193,6 +242,7 @@ impl fmt::Display for Error {
             Error::Json(ref e) => write!(f, "parsing JSON failed: {}", e),
             Error::Minreq(ref e) => write!(f, "minreq: {}", e),
             Error::Http(ref e) => write!(f, "http ({})", e),
+            Error::InvalidBearerToken => write!(f, "bearer token contains a line break"),
         }
     }
 }
@@ -205,6 +255,7 @@ impl error::Error for Error {
             Json(ref e) => Some(e),
             Minreq(ref e) => Some(e),
             Http(ref e) => Some(e),
+            InvalidBearerToken => None,
         }
     }
 }
@@ -267,4 +318,11 @@ mod tests {
             .build();
         let _ = Client::with_transport(tp);
     }
+
+    #[test]
+    fn bearer_auth() {
+        let tp = Builder::new().bearer_auth("t0ken").unwrap().build();
+        assert_eq!(tp.authorization, Some("Bearer t0ken".to_owned()));
+        assert!(matches!(Builder::new().bearer_auth("t0ken\r\n"), Err(Error::InvalidBearerToken)));
+    }
 }
@@ -21,9 +21,28 @@ pub extern crate base64;
 #[cfg(feature = "minreq")]
 pub extern crate minreq;
 
+/// Logs a message at debug level if the `log` feature is enabled.
+///
+/// The messages must not contain credentials or request and response bodies.
+#[allow(unused_macros)] // Only used by the HTTP transports.
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "log")]
+        log::debug!($($arg)*);
+    };
+}
+
+pub mod caching;
 pub mod client;
 pub mod error;
+pub mod fallback;
+#[cfg(feature = "test-utils")]
+pub mod fault_injection;
 pub mod http;
+pub mod single_flight;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod testing;
+pub mod timeout;
 
 #[cfg(feature = "minreq_http")]
 pub use http::minreq_http;
@@ -36,12 +55,132 @@ pub mod simple_tcp;
 #[cfg(all(feature = "simple_uds", not(windows)))]
 pub mod simple_uds;
 
+#[cfg(feature = "simple_stdio")]
+pub mod simple_stdio;
+
 use serde::{Deserialize, Serialize};
 use serde_json::value::RawValue;
 
 pub use crate::client::{Client, Transport};
 pub use crate::error::Error;
 
+/// Opens a TCP connection to `addr` from the local address `bind_addr`.
+#[cfg(feature = "bind_addr")]
+pub(crate) fn connect_from(
+    bind_addr: std::net::SocketAddr,
+    addr: &std::net::SocketAddr,
+    timeout: Option<std::time::Duration>,
+) -> std::io::Result<std::net::TcpStream> {
+    use socket2::{Domain, Protocol, Socket, Type};
+
+    let sock = Socket::new(Domain::for_address(*addr), Type::STREAM, Some(Protocol::TCP))?;
+    sock.bind(&bind_addr.into())?;
+    match timeout {
+        Some(timeout) => sock.connect_timeout(&(*addr).into(), timeout)?,
+        None => sock.connect(&(*addr).into())?,
+    }
+    Ok(sock.into())
+}
+
+/// A socket whose read timeout can be changed, as used by [`DeadlineReader`].
+#[cfg(any(feature = "simple_tcp", all(feature = "simple_uds", not(windows))))]
+pub(crate) trait SetReadTimeout: std::io::Read {
+    /// Sets the read timeout of the socket.
+    fn set_read_timeout(&self, timeout: Option<std::time::Duration>) -> std::io::Result<()>;
+}
+
+#[cfg(feature = "simple_tcp")]
+impl SetReadTimeout for std::net::TcpStream {
+    fn set_read_timeout(&self, timeout: Option<std::time::Duration>) -> std::io::Result<()> {
+        std::net::TcpStream::set_read_timeout(self, timeout)
+    }
+}
+
+#[cfg(all(feature = "simple_uds", not(windows)))]
+impl SetReadTimeout for std::os::unix::net::UnixStream {
+    fn set_read_timeout(&self, timeout: Option<std::time::Duration>) -> std::io::Result<()> {
+        std::os::unix::net::UnixStream::set_read_timeout(self, timeout)
+    }
+}
+
+/// A reader which fails with [`std::io::ErrorKind::TimedOut`] once a deadline has passed.
+///
+/// Before each read, the read timeout of the socket is shortened to the time left, so that a
+/// server sending a response very slowly can't make reading it outlast the deadline.
+#[cfg(any(feature = "simple_tcp", all(feature = "simple_uds", not(windows))))]
+pub(crate) struct DeadlineReader<'a, S> {
+    pub(crate) sock: &'a mut S,
+    pub(crate) deadline: std::time::Instant,
+    /// The read timeout of the socket, which still applies to each read.
+    pub(crate) timeout: Option<std::time::Duration>,
+}
+
+#[cfg(any(feature = "simple_tcp", all(feature = "simple_uds", not(windows))))]
+impl<S: SetReadTimeout> std::io::Read for DeadlineReader<'_, S> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let left = self.deadline.saturating_duration_since(std::time::Instant::now());
+        if left == std::time::Duration::ZERO {
+            return Err(std::io::ErrorKind::TimedOut.into());
+        }
+        let timeout = self.timeout.map_or(left, |timeout| timeout.min(left));
+        self.sock.set_read_timeout(Some(timeout))?;
+        self.sock.read(buf)
+    }
+}
+
+/// Reads a single JSON value from `reader`, up to a newline if `newline_framing` is set.
+///
+/// Returns `None` if the connection is closed before anything is received.
+#[cfg(any(feature = "simple_tcp", all(feature = "simple_uds", not(windows))))]
+pub(crate) fn read_response<R, T>(
+    reader: R,
+    newline_framing: bool,
+) -> Result<Option<T>, serde_json::Error>
+where
+    R: std::io::Read,
+    T: for<'a> serde::de::Deserialize<'a>,
+{
+    use std::io::BufRead;
+
+    if newline_framing {
+        let mut line = Vec::new();
+        std::io::BufReader::new(reader)
+            .read_until(b'\n', &mut line)
+            .map_err(serde_json::Error::io)?;
+        if line.is_empty() {
+            return Ok(None);
+        }
+        return serde_json::from_slice(&line).map(Some);
+    }
+    serde_json::Deserializer::from_reader(reader).into_iter().next().transpose()
+}
+
+/// Reads from `sock` with `read`, through a [`DeadlineReader`] if there is a `deadline`.
+///
+/// Once the deadline has passed, any error is replaced with `timed_out`: it is a read which
+/// timed out because its timeout was shortened to the time left.
+#[cfg(any(feature = "simple_tcp", all(feature = "simple_uds", not(windows))))]
+pub(crate) fn read_with_deadline<S, T, E, F>(
+    sock: &mut S,
+    timeout: Option<std::time::Duration>,
+    deadline: Option<std::time::Instant>,
+    timed_out: E,
+    read: F,
+) -> Result<T, E>
+where
+    S: SetReadTimeout,
+    F: FnOnce(&mut dyn std::io::Read) -> Result<T, E>,
+{
+    let res = match deadline {
+        Some(deadline) => read(&mut DeadlineReader { sock, deadline, timeout }),
+        None => read(sock),
+    };
+    match res {
+        Err(_) if deadline.map_or(false, |d| std::time::Instant::now() >= d) => Err(timed_out),
+        res => res,
+    }
+}
+
 /// Shorthand method to convert an argument into a boxed [`serde_json::value::RawValue`].
 ///
 /// Since serializers rarely fail, it's probably easier to use [`arg`] instead.
@@ -68,7 +207,11 @@ pub fn arg<T: serde::Serialize>(arg: T) -> Box<RawValue> {
 pub struct Request<'a> {
     /// The name of the RPC call.
     pub method: &'a str,
-    /// Parameters to the RPC call.
+    /// Parameters to the RPC call, omitted from the request if [`None`].
+    ///
+    /// Up to version 0.18.0, [`None`] was sent as `"params": null`; to send that, set a `null`
+    /// value instead.
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub params: Option<&'a RawValue>,
     /// Identifier for this request, which should appear in the response.
     pub id: serde_json::Value,
@@ -76,14 +219,60 @@ pub struct Request<'a> {
     pub jsonrpc: Option<&'a str>,
 }
 
+impl<'a> Request<'a> {
+    /// Serializes the request into the JSON bytes sent over the wire.
+    ///
+    /// The transports of this crate serialize requests in the same way, so these are exactly the
+    /// bytes of the request as sent, e.g. to compute a signature over them.
+    pub fn to_vec(&self) -> Result<Vec<u8>, Error> { Ok(serde_json::to_vec(self)?) }
+
+    /// Replaces the ID of the request, e.g. the nonce generated by [`Client::build_request`],
+    /// with an ID chosen by the caller, such as a correlation ID dictated by another system.
+    ///
+    /// Check the response against this ID with [`Response::into_result`]. Keeping the IDs of
+    /// concurrent requests, or of requests in the same batch, unique is then up to the caller.
+    pub fn with_id(self, id: serde_json::Value) -> Request<'a> { Request { id, ..self } }
+}
+
+/// A JSONRPC notification, i.e. a request without an ID, to which no response is expected.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Notification {
+    /// The name of the RPC call.
+    pub method: String,
+    /// Parameters to the RPC call, omitted from the notification if [`None`], as for
+    /// [`Request::params`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub params: Option<Box<RawValue>>,
+    /// jsonrpc field, MUST be "2.0".
+    pub jsonrpc: Option<String>,
+}
+
+/// An entry of a batch sent with [`Client::send_mixed_batch`]: a request or a notification.
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum BatchEntry<'a> {
+    /// A request, to which the server responds.
+    Request(Request<'a>),
+    /// A notification, to which the server doesn't respond.
+    Notification(Notification),
+}
+
 /// A JSONRPC response object.
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Response {
     /// A result if there is one, or [`None`].
     pub result: Option<Box<RawValue>>,
     /// An error if there is one, or [`None`].
+    #[cfg_attr(
+        feature = "lenient_rpc_errors",
+        serde(default, deserialize_with = "error::deserialize_lenient_rpc_error")
+    )]
     pub error: Option<error::RpcError>,
     /// Identifier for this response, which should match that of the request.
+    ///
+    /// Some servers omit it, in which case it is `null`, so that the response still parses and
+    /// is rejected by the client with [`Error::NonceMismatch`], like any other mismatched ID.
+    #[serde(default)]
     pub id: serde_json::Value,
     /// jsonrpc field, MUST be "2.0".
     pub jsonrpc: Option<String>,
@@ -103,6 +292,93 @@ impl Response {
         }
     }
 
+    /// Checks that this is the response to the request with ID `expected_id` and extracts its
+    /// result.
+    ///
+    /// A transport returns a response carrying an RPC error as `Ok`, since it was received
+    /// successfully. This applies the stricter definition of success of [`Client::call`]: the
+    /// response fails with [`Error::VersionMismatch`] if its `jsonrpc` field is set to anything
+    /// but `"2.0"`, with [`Error::NonceMismatch`] if its ID doesn't match and with [`Error::Rpc`]
+    /// if it carries an error. Wrappers around transports can use it to classify responses the
+    /// same way.
+    pub fn into_result<T: for<'a> serde::de::Deserialize<'a>>(
+        self,
+        expected_id: &serde_json::Value,
+    ) -> Result<T, Error> {
+        self.into_result_with_versions(expected_id, &["2.0"])
+    }
+
+    /// Same as [`Response::into_result`], but accepting any of `accepted_versions` in the
+    /// `jsonrpc` field, as set with [`Client::accept_jsonrpc_versions`].
+    pub fn into_result_with_versions<T, S>(
+        self,
+        expected_id: &serde_json::Value,
+        accepted_versions: &[S],
+    ) -> Result<T, Error>
+    where
+        T: for<'a> serde::de::Deserialize<'a>,
+        S: AsRef<str>,
+    {
+        self.check_version(accepted_versions)?;
+        if self.id != *expected_id {
+            return Err(Error::NonceMismatch);
+        }
+        if let Some(e) = self.error {
+            return Err(Error::Rpc(e));
+        }
+        let result = self.result.as_deref().map_or("null", RawValue::get);
+        serde_json::from_str(result).map_err(Error::Json)
+    }
+
+    /// Fails with [`Error::VersionMismatch`] if the `jsonrpc` field is set to a version which
+    /// isn't accepted. A response without the field is always accepted.
+    pub(crate) fn check_version<S: AsRef<str>>(
+        &self,
+        accepted_versions: &[S],
+    ) -> Result<(), Error> {
+        match self.jsonrpc {
+            Some(ref v) if !accepted_versions.iter().any(|a| a.as_ref() == v) =>
+                Err(Error::VersionMismatch),
+            _ => Ok(()),
+        }
+    }
+
+    /// Takes the raw result out of a response, if there is one.
+    ///
+    /// Returns the RPC error if there was one. Unlike [`Response::result`], a missing result is
+    /// returned as [`None`] rather than as `null`.
+    pub fn take_result(self) -> Result<Option<Box<RawValue>>, Error> {
+        match self.error {
+            Some(e) => Err(Error::Rpc(e)),
+            None => Ok(self.result),
+        }
+    }
+
+    /// Extracts the result from a response, borrowing from it where possible.
+    ///
+    /// Unlike [`Response::result`], this allows deserializing into types with borrowed fields
+    /// such as `&str` or `Cow<str>`, avoiding copies of large results.
+    pub fn borrowed_result<'a, T: serde::de::Deserialize<'a>>(&'a self) -> Result<T, Error> {
+        if let Some(ref e) = self.error {
+            return Err(Error::Rpc(e.clone()));
+        }
+
+        let result = self.result.as_deref().map_or("null", RawValue::get);
+        serde_json::from_str(result).map_err(Error::Json)
+    }
+
+    /// Returns an iterator deserializing the elements of an array result one at a time.
+    ///
+    /// This avoids holding all elements of a large array, e.g. the result of `listunspent`, in
+    /// memory at once. Only the array itself is parsed upfront, the elements are parsed as the
+    /// iterator is advanced.
+    pub fn result_iter<'a, T: serde::de::Deserialize<'a>>(
+        &'a self,
+    ) -> Result<impl Iterator<Item = Result<T, Error>> + 'a, Error> {
+        let elements: Vec<&'a RawValue> = self.borrowed_result()?;
+        Ok(elements.into_iter().map(|e| serde_json::from_str(e.get()).map_err(Error::Json)))
+    }
+
     /// Returns the RPC error, if there was one, but does not check the result.
     pub fn check_error(self) -> Result<(), Error> {
         if let Some(e) = self.error {
@@ -112,6 +388,15 @@ impl Response {
         }
     }
 
+    /// Returns the standard error this response carries, if its error has one of the codes
+    /// reserved by the JSON-RPC specification.
+    ///
+    /// This is the inverse of [`error::standard_error`], e.g. for asserting in the tests of a
+    /// server which kind of error it responded with.
+    pub fn as_standard_error(&self) -> Option<error::StandardError> {
+        self.error.as_ref().and_then(|e| error::StandardError::from_code(e.code))
+    }
+
     /// Returns whether or not the `result` field is empty.
     pub fn is_none(&self) -> bool { self.result.is_none() }
 }
@@ -122,6 +407,133 @@ mod tests {
     use serde_json::value::{to_raw_value, RawValue};
 
     use super::*;
+    use crate::testing::{response, FnTransport};
+
+    #[test]
+    fn request_to_vec() {
+        let params = to_raw_value(&[1]).unwrap();
+        let req = Request {
+            method: "getblockhash",
+            params: Some(&params),
+            id: json!(1),
+            jsonrpc: Some("2.0"),
+        };
+        assert_eq!(
+            req.to_vec().unwrap(),
+            br#"{"method":"getblockhash","params":[1],"id":1,"jsonrpc":"2.0"}"#
+        );
+    }
+
+    #[test]
+    fn request_without_params() {
+        // Up to 0.18.0, this was serialized with `"params":null`.
+        let req = Request { method: "getnetworkinfo", params: None, id: json!(1), jsonrpc: None };
+        assert_eq!(req.to_vec().unwrap(), br#"{"method":"getnetworkinfo","id":1,"jsonrpc":null}"#);
+
+        let null = to_raw_value(&serde_json::Value::Null).unwrap();
+        let req = Request { params: Some(&null), ..req };
+        let expected = br#"{"method":"getnetworkinfo","params":null,"id":1,"jsonrpc":null}"#;
+        assert_eq!(req.to_vec().unwrap(), expected);
+    }
+
+    #[test]
+    fn request_with_id() {
+        let client = Client::with_transport(echo_id_transport());
+        let id = json!("correlation-7");
+        let req = client.build_request("test", None).with_id(id.clone());
+        assert_eq!(req.id, id);
+        let resp = client.send_request(req).unwrap();
+        assert_eq!(resp.into_result::<serde_json::Value>(&id).unwrap(), id);
+    }
+
+    /// A transport answering each request with its ID as the result.
+    fn echo_id_transport() -> FnTransport {
+        FnTransport::new(|req| Ok(response(req.id.clone(), &req.id.to_string())))
+    }
+
+    #[test]
+    fn take_result() {
+        let resp = Response {
+            result: Some(to_raw_value(&1).unwrap()),
+            error: None,
+            id: From::from(1),
+            jsonrpc: Some(String::from("2.0")),
+        };
+        assert_eq!(resp.clone().take_result().unwrap().unwrap().get(), "1");
+
+        let resp = Response { result: None, ..resp };
+        assert!(resp.clone().take_result().unwrap().is_none());
+
+        let err = error::RpcError { code: -1, message: "error".to_owned(), data: None };
+        let resp = Response { error: Some(err), ..resp };
+        assert!(matches!(resp.take_result(), Err(Error::Rpc(_))));
+    }
+
+    #[test]
+    fn into_result() {
+        let resp = Response {
+            result: Some(to_raw_value(&1).unwrap()),
+            error: None,
+            id: From::from(1),
+            jsonrpc: Some(String::from("2.0")),
+        };
+        assert_eq!(resp.clone().into_result::<u8>(&json!(1)).unwrap(), 1);
+        assert!(matches!(resp.clone().into_result::<u8>(&json!(2)), Err(Error::NonceMismatch)));
+        assert!(matches!(resp.clone().into_result::<String>(&json!(1)), Err(Error::Json(_))));
+
+        let old = Response { jsonrpc: Some(String::from("1.0")), ..resp.clone() };
+        assert!(matches!(old.clone().into_result::<u8>(&json!(1)), Err(Error::VersionMismatch)));
+        assert_eq!(old.into_result_with_versions::<u8, _>(&json!(1), &["1.0", "2.0"]).unwrap(), 1);
+        let none = Response { jsonrpc: None, ..resp.clone() };
+        assert_eq!(none.into_result::<u8>(&json!(1)).unwrap(), 1);
+
+        let err = error::RpcError { code: -1, message: "error".to_owned(), data: None };
+        let resp = Response { result: None, error: Some(err), ..resp };
+        assert!(matches!(resp.into_result::<u8>(&json!(1)), Err(Error::Rpc(_))));
+    }
+
+    #[test]
+    fn result_iter() {
+        let resp = Response {
+            result: Some(to_raw_value(&json!([1, 2, "three"])).unwrap()),
+            error: None,
+            id: From::from(1),
+            jsonrpc: Some(String::from("2.0")),
+        };
+        let mut iter = resp.result_iter::<u32>().unwrap();
+        assert_eq!(iter.next().unwrap().unwrap(), 1);
+        assert_eq!(iter.next().unwrap().unwrap(), 2);
+        assert!(matches!(iter.next(), Some(Err(Error::Json(_)))));
+        assert!(iter.next().is_none());
+        drop(iter);
+
+        let resp = Response { result: Some(to_raw_value(&json!({})).unwrap()), ..resp };
+        assert!(resp.result_iter::<u32>().is_err());
+    }
+
+    #[test]
+    fn borrowed_result() {
+        use std::borrow::Cow;
+
+        #[derive(Deserialize)]
+        struct Borrowed<'a> {
+            #[serde(borrow)]
+            hex: Cow<'a, str>,
+        }
+
+        let resp = Response {
+            result: Some(to_raw_value(&json!({ "hex": "00ff" })).unwrap()),
+            error: None,
+            id: From::from(1),
+            jsonrpc: Some(String::from("2.0")),
+        };
+        let result: Borrowed = resp.borrowed_result().unwrap();
+        assert!(matches!(result.hex, Cow::Borrowed("00ff")));
+        assert_eq!(resp.borrowed_result::<&str>().ok(), None);
+
+        let resp = Response { result: None, ..resp };
+        assert_eq!(resp.borrowed_result::<Option<&str>>().unwrap(), None);
+    }
 
     #[test]
     fn response_is_none() {
@@ -174,6 +586,45 @@ mod tests {
         assert!(recovered2.is_err());
     }
 
+    #[test]
+    fn error_object() {
+        let s = r#"{"error":{"code":-32601,"message":"Method not found","data":[1]},"id":1}"#;
+        let response: Response = serde_json::from_str(s).unwrap();
+        let error = response.error.unwrap();
+        assert_eq!(error.code, -32601);
+        assert_eq!(error.message, "Method not found");
+        assert_eq!(error.data.unwrap().get(), "[1]");
+
+        let s = r#"{"result":1,"id":1}"#;
+        let response: Response = serde_json::from_str(s).unwrap();
+        assert!(response.error.is_none());
+    }
+
+    #[test]
+    fn error_string() {
+        let s = r#"{"result":null,"error":"something went wrong","id":1}"#;
+        let response = serde_json::from_str::<Response>(s);
+        #[cfg(feature = "lenient_rpc_errors")]
+        {
+            let error = response.unwrap().error.unwrap();
+            assert_eq!(error.code, 0);
+            assert_eq!(error.message, "something went wrong");
+            assert!(error.data.is_none());
+        }
+        #[cfg(not(feature = "lenient_rpc_errors"))]
+        assert!(response.is_err());
+    }
+
+    #[test]
+    fn missing_id() {
+        let s = r#"{"jsonrpc":"2.0","result":true}"#;
+        let response: Response = serde_json::from_str(s).unwrap();
+        assert_eq!(response.id, serde_json::Value::Null);
+        assert_eq!(response.result.as_ref().unwrap().get(), "true");
+        // It doesn't match the ID of any request.
+        assert!(matches!(response.into_result::<bool>(&json!(1)), Err(Error::NonceMismatch)));
+    }
+
     #[test]
     fn batch_response() {
         // from the jsonrpc.org spec example
@@ -0,0 +1,187 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! This module implements a synchronous transport over the standard input and output of a child
+//! process, exchanging newline-delimited JSON messages with it.
+
+use std::ffi::OsStr;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::Mutex;
+use std::{error, fmt, io};
+
+use crate::client::Transport;
+use crate::{BatchEntry, Request, Response};
+
+/// Simple synchronous transport to a child process over its stdin and stdout.
+///
+/// Each request is written to the process as a single line of JSON, and the next line it writes
+/// is read as the response. Requests are sent one at a time.
+pub struct StdioTransport {
+    /// The spawned process, if the transport spawned it.
+    child: Option<Child>,
+    /// The pipes to the process, locked for the duration of a request.
+    pipes: Mutex<(ChildStdin, BufReader<ChildStdout>)>,
+}
+
+impl StdioTransport {
+    /// Spawns `command` with the given arguments and creates a transport talking to it.
+    ///
+    /// The stderr of the process is inherited. The process is killed when the transport is
+    /// dropped.
+    pub fn spawn<S, I, A>(command: S, args: I) -> Result<StdioTransport, Error>
+    where
+        S: AsRef<OsStr>,
+        I: IntoIterator<Item = A>,
+        A: AsRef<OsStr>,
+    {
+        let mut child = Command::new(command)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        // We asked for both pipes above, so they are present.
+        let stdin = child.stdin.take().expect("piped stdin");
+        let stdout = child.stdout.take().expect("piped stdout");
+        let pipes = Mutex::new((stdin, BufReader::new(stdout)));
+        Ok(StdioTransport { child: Some(child), pipes })
+    }
+
+    /// Creates a transport talking to an already spawned process over the given pipes.
+    pub fn new(stdin: ChildStdin, stdout: ChildStdout) -> StdioTransport {
+        StdioTransport { child: None, pipes: Mutex::new((stdin, BufReader::new(stdout))) }
+    }
+
+    fn request<R>(&self, req: impl serde::Serialize) -> Result<R, Error>
+    where
+        R: for<'a> serde::de::Deserialize<'a>,
+    {
+        // No part of this codebase should panic, so unwrapping a mutex lock is fine
+        let mut pipes = self.pipes.lock().expect("poisoned mutex");
+        let (ref mut stdin, ref mut stdout) = *pipes;
+
+        let mut line = serde_json::to_vec(&req)?;
+        line.push(b'\n');
+        stdin.write_all(&line)?;
+        stdin.flush()?;
+
+        // NOTE: we don't check the id there, so it *must* be synchronous
+        let mut line = String::new();
+        while line.trim().is_empty() {
+            line.clear();
+            if stdout.read_line(&mut line)? == 0 {
+                return Err(Error::Closed);
+            }
+        }
+        Ok(serde_json::from_str(&line)?)
+    }
+}
+
+impl Transport for StdioTransport {
+    fn send_request(&self, req: Request) -> Result<Response, crate::Error> {
+        Ok(self.request(req)?)
+    }
+
+    fn send_batch(&self, reqs: &[Request]) -> Result<Vec<Response>, crate::Error> {
+        Ok(self.request(reqs)?)
+    }
+
+    fn send_mixed_batch(&self, entries: &[BatchEntry]) -> Result<Vec<Response>, crate::Error> {
+        Ok(self.request(entries)?)
+    }
+
+    fn fmt_target(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.child {
+            Some(ref child) => write!(f, "stdio of process {}", child.id()),
+            None => f.write_str("stdio"),
+        }
+    }
+}
+
+impl Drop for StdioTransport {
+    fn drop(&mut self) {
+        if let Some(ref mut child) = self.child {
+            // The process may have exited already, in which case there is nothing to do.
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+impl fmt::Debug for StdioTransport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("StdioTransport").field("child", &self.child).finish_non_exhaustive()
+    }
+}
+
+/// Error that can occur while using the stdio transport.
+#[derive(Debug)]
+pub enum Error {
+    /// An error occurred when spawning the process or talking to it.
+    Io(io::Error),
+    /// The process closed its stdout before sending a response.
+    Closed,
+    /// JSON parsing error.
+    Json(serde_json::Error),
+}
+
+impl Error {
+    /// Returns whether the request may succeed if sent again, see [`crate::Error::is_retryable`].
+    pub(crate) fn is_retryable(&self) -> bool { matches!(*self, Error::Io(_) | Error::Closed) }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        use Error::*;
+
+        match *self {
+            Io(ref e) => write!(f, "couldn't talk to process: {}", e),
+            Closed => f.write_str("process closed its output before responding"),
+            Json(ref e) => write!(f, "JSON error: {}", e),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        use self::Error::*;
+
+        match *self {
+            Io(ref e) => Some(e),
+            Closed => None,
+            Json(ref e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self { Error::Io(e) }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self { Error::Json(e) }
+}
+
+impl From<Error> for crate::Error {
+    fn from(e: Error) -> crate::Error {
+        match e {
+            Error::Json(e) => crate::Error::Json(e),
+            e => crate::Error::Transport(Box::new(e)),
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::Client;
+
+    #[test]
+    fn sanity_check_stdio_transport() {
+        let script = r#"read req; echo '{"result":"pong","error":null,"id":1}'"#;
+        let client = Client::with_transport(StdioTransport::spawn("sh", ["-c", script]).unwrap());
+        assert_eq!(client.call::<String>("ping", None).unwrap(), "pong");
+
+        // The script exited after one response.
+        assert!(matches!(client.call::<String>("ping", None), Err(crate::Error::Transport(_))));
+    }
+}
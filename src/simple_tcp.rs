@@ -3,10 +3,18 @@
 //! This module implements a synchronous transport over a raw [`std::net::TcpListener`].
 //! Note that it does not handle TCP over Unix Domain Sockets, see `simple_uds` for this.
 
+use std::collections::VecDeque;
+use std::io::Write;
 use std::{error, fmt, io, net, time};
 
+use serde::Deserialize;
+use serde_json::value::RawValue;
+
 use crate::client::Transport;
-use crate::{Request, Response};
+use crate::{read_response, read_with_deadline, BatchEntry, Notification, Request, Response};
+
+/// The maximum number of notifications [`Subscription::drain_until`] buffers.
+const MAX_PENDING_NOTIFICATIONS: usize = 1000;
 
 #[derive(Debug, Clone)]
 /// Simple synchronous TCP transport.
@@ -15,29 +23,214 @@ pub struct TcpTransport {
     pub addr: net::SocketAddr,
     /// The read and write timeout to use for this connection.
     pub timeout: Option<time::Duration>,
+    /// The time after which a request fails with [`Error::Timeout`], however fast each read is.
+    deadline: Option<time::Duration>,
+    /// Whether requests are terminated with a newline, and responses read up to one, for
+    /// servers which exchange newline-delimited JSON.
+    newline_framing: bool,
+    /// The local address to connect from, if not the default one.
+    #[cfg(feature = "bind_addr")]
+    bind_addr: Option<net::SocketAddr>,
 }
 
 impl TcpTransport {
     /// Creates a new `TcpTransport` without timeouts.
-    pub fn new(addr: net::SocketAddr) -> TcpTransport { TcpTransport { addr, timeout: None } }
+    pub fn new(addr: net::SocketAddr) -> TcpTransport {
+        TcpTransport {
+            addr,
+            timeout: None,
+            deadline: None,
+            newline_framing: false,
+            #[cfg(feature = "bind_addr")]
+            bind_addr: None,
+        }
+    }
+
+    /// Sets the time after which a request fails with [`Error::Timeout`], however fast each
+    /// read is, so that a server trickling out a response can't hold the request indefinitely.
+    /// No deadline is set by default.
+    pub fn deadline(mut self, deadline: time::Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Sets whether requests are terminated with a newline, and responses read up to one, for
+    /// servers which exchange newline-delimited JSON rather than bare JSON values. Defaults to
+    /// `false`.
+    pub fn newline_framing(mut self, newline_framing: bool) -> Self {
+        self.newline_framing = newline_framing;
+        self
+    }
+
+    /// Sets the local address to connect to the server from, e.g. to pick the network
+    /// interface used on a multi-homed host.
+    #[cfg(feature = "bind_addr")]
+    pub fn bind_addr(mut self, bind_addr: net::SocketAddr) -> Self {
+        self.bind_addr = Some(bind_addr);
+        self
+    }
+
+    /// Opens a connection to the server.
+    fn connect(&self) -> Result<net::TcpStream, Error> {
+        #[cfg(feature = "bind_addr")]
+        if let Some(bind_addr) = self.bind_addr {
+            return Ok(crate::connect_from(bind_addr, &self.addr, self.timeout)?);
+        }
+        Ok(net::TcpStream::connect(self.addr)?)
+    }
 
     fn request<R>(&self, req: impl serde::Serialize) -> Result<R, Error>
     where
         R: for<'a> serde::de::Deserialize<'a>,
     {
-        let mut sock = net::TcpStream::connect(self.addr)?;
+        let deadline = self.deadline.map(|d| time::Instant::now() + d);
+        let mut sock = self.connect()?;
         sock.set_read_timeout(self.timeout)?;
         sock.set_write_timeout(self.timeout)?;
 
         serde_json::to_writer(&mut sock, &req)?;
+        if self.newline_framing {
+            sock.write_all(b"\n")?;
+        }
 
         // NOTE: we don't check the id there, so it *must* be synchronous
-        let resp: R = serde_json::Deserializer::from_reader(&mut sock)
-            .into_iter()
-            .next()
-            .ok_or(Error::Timeout)??;
-        Ok(resp)
+        let framing = self.newline_framing;
+        read_with_deadline(&mut sock, self.timeout, deadline, Error::Timeout, |reader| {
+            read_response(reader, framing)?.ok_or(Error::Timeout)
+        })
     }
+
+    /// Sends a subscription request and returns the notifications the server pushes afterwards.
+    ///
+    /// This opens a connection which is kept open for as long as the returned [`Subscription`]
+    /// lives. Messages which aren't notifications, such as the response to the subscription
+    /// request itself, are skipped. The configured timeout applies to waiting for each message.
+    pub fn subscribe(&self, req: Request) -> Result<Subscription, Error> {
+        let mut sock = self.connect()?;
+        sock.set_read_timeout(self.timeout)?;
+        sock.set_write_timeout(self.timeout)?;
+
+        serde_json::to_writer(&mut sock, &req)?;
+        if self.newline_framing {
+            sock.write_all(b"\n")?;
+        }
+
+        let sock_handle = sock.try_clone()?;
+        let stream = serde_json::Deserializer::from_reader(io::BufReader::new(sock)).into_iter();
+        Ok(Subscription {
+            stream,
+            sock: sock_handle,
+            timeout: self.timeout,
+            pending: VecDeque::new(),
+        })
+    }
+}
+
+/// Any message received over a [`Subscription`].
+#[derive(Deserialize)]
+struct Message {
+    method: Option<String>,
+    params: Option<Box<RawValue>>,
+    id: Option<serde_json::Value>,
+    jsonrpc: Option<String>,
+}
+
+/// An iterator over the notifications a server pushes over a persistent TCP connection.
+///
+/// Created by [`TcpTransport::subscribe`]. The iterator ends when the server closes the
+/// connection.
+pub struct Subscription {
+    stream: serde_json::StreamDeserializer<
+        'static,
+        serde_json::de::IoRead<io::BufReader<net::TcpStream>>,
+        Message,
+    >,
+    /// The socket read by `stream`, to change its read timeout.
+    sock: net::TcpStream,
+    /// The read timeout of the transport.
+    timeout: Option<time::Duration>,
+    /// Notifications received while draining responses, not yet yielded.
+    pending: VecDeque<Notification>,
+}
+
+impl Subscription {
+    /// Reads and discards responses until the one with the given ID.
+    ///
+    /// This keeps the connection in sync when the response to a request is not of interest.
+    /// Notifications received in the meantime are not lost, but yielded by the iterator
+    /// afterwards. Fails if the connection is closed before the response arrives, and with
+    /// [`Error::Timeout`] if it doesn't arrive within `timeout` or if too many notifications
+    /// arrive before it. After a timeout, the connection is out of sync, so the subscription
+    /// should be dropped.
+    pub fn drain_until(
+        &mut self,
+        id: &serde_json::Value,
+        timeout: time::Duration,
+    ) -> Result<(), Error> {
+        let deadline = time::Instant::now() + timeout;
+        let res = self.drain_until_deadline(id, deadline);
+        self.sock.set_read_timeout(self.timeout)?;
+        match res {
+            // A read timed out because we shortened its timeout to the time left.
+            Err(_) if time::Instant::now() >= deadline => Err(Error::Timeout),
+            res => res,
+        }
+    }
+
+    /// Does the work of [`Subscription::drain_until`].
+    fn drain_until_deadline(
+        &mut self,
+        id: &serde_json::Value,
+        deadline: time::Instant,
+    ) -> Result<(), Error> {
+        loop {
+            let left = deadline.saturating_duration_since(time::Instant::now());
+            if left == time::Duration::ZERO {
+                return Err(Error::Timeout);
+            }
+            self.sock.set_read_timeout(Some(self.timeout.map_or(left, |t| t.min(left))))?;
+
+            let msg = match self.stream.next() {
+                Some(msg) => msg?,
+                None => return Err(Error::SocketError(io::ErrorKind::UnexpectedEof.into())),
+            };
+            match (msg.method, msg.id) {
+                (_, Some(ref msg_id)) if msg_id == id => return Ok(()),
+                (Some(_), None) if self.pending.len() >= MAX_PENDING_NOTIFICATIONS =>
+                    return Err(Error::Timeout),
+                (Some(method), None) => self.pending.push_back(Notification {
+                    method,
+                    params: msg.params,
+                    jsonrpc: msg.jsonrpc,
+                }),
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Iterator for Subscription {
+    type Item = Result<Notification, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(notification) = self.pending.pop_front() {
+            return Some(Ok(notification));
+        }
+        loop {
+            let msg = match self.stream.next()? {
+                Ok(msg) => msg,
+                Err(e) => return Some(Err(e.into())),
+            };
+            // A message with an ID is a response to a request rather than a notification.
+            if let (Some(method), None) = (msg.method, msg.id) {
+                return Some(Ok(Notification { method, params: msg.params, jsonrpc: msg.jsonrpc }));
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Subscription {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { f.write_str("Subscription { .. }") }
 }
 
 impl Transport for TcpTransport {
@@ -49,7 +242,16 @@ impl Transport for TcpTransport {
         Ok(self.request(reqs)?)
     }
 
+    fn send_mixed_batch(&self, entries: &[BatchEntry]) -> Result<Vec<Response>, crate::Error> {
+        Ok(self.request(entries)?)
+    }
+
     fn fmt_target(&self, f: &mut fmt::Formatter) -> fmt::Result { write!(f, "{}", self.addr) }
+
+    fn check_connection(&self) -> Result<(), crate::Error> {
+        self.connect()?;
+        Ok(())
+    }
 }
 
 /// Error that can occur while using the TCP transport.
@@ -63,6 +265,13 @@ pub enum Error {
     Json(serde_json::Error),
 }
 
+impl Error {
+    /// Returns whether the request may succeed if sent again, see [`crate::Error::is_retryable`].
+    pub(crate) fn is_retryable(&self) -> bool {
+        matches!(*self, Error::SocketError(_) | Error::Timeout)
+    }
+}
+
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         use Error::*;
@@ -92,7 +301,13 @@ impl From<io::Error> for Error {
 }
 
 impl From<serde_json::Error> for Error {
-    fn from(e: serde_json::Error) -> Self { Error::Json(e) }
+    fn from(e: serde_json::Error) -> Self {
+        if e.is_io() {
+            Error::SocketError(e.into())
+        } else {
+            Error::Json(e)
+        }
+    }
 }
 
 impl From<Error> for crate::Error {
@@ -106,53 +321,201 @@ impl From<Error> for crate::Error {
 
 #[cfg(test)]
 mod tests {
-    use std::io::{Read, Write};
+    use std::io::Write;
     use std::thread;
 
     use super::*;
+    use crate::testing::{EchoServer, Protocol};
     use crate::Client;
 
     // Test a dummy request / response over a raw TCP transport
     #[test]
     fn sanity_check_tcp_transport() {
+        let server = EchoServer::tcp(Protocol::Raw).unwrap();
+        let transport = TcpTransport {
+            timeout: Some(time::Duration::from_secs(5)),
+            ..TcpTransport::new(server.socket_addr().unwrap())
+        };
+        let client = Client::with_transport(transport);
+
+        let params = crate::arg([4242242]);
+        let request = client.build_request("arandommethod", Some(&params));
+        let response = client.send_request(request.clone()).unwrap();
+        assert_eq!(response.id, request.id);
+        assert_eq!(response.result.unwrap().get(), "[4242242]");
+    }
+
+    #[test]
+    fn subscribe() {
         let addr: net::SocketAddr =
             net::SocketAddrV4::new(net::Ipv4Addr::new(127, 0, 0, 1), 0).into();
         let server = net::TcpListener::bind(addr).unwrap();
         let addr = server.local_addr().unwrap();
-        let dummy_req = Request {
-            method: "arandommethod",
-            params: None,
-            id: serde_json::Value::Number(4242242.into()),
-            jsonrpc: Some("2.0"),
-        };
-        let dummy_req_ser = serde_json::to_vec(&dummy_req).unwrap();
-        let dummy_resp = Response {
-            result: None,
-            error: None,
-            id: serde_json::Value::Number(4242242.into()),
-            jsonrpc: Some("2.0".into()),
-        };
-        let dummy_resp_ser = serde_json::to_vec(&dummy_resp).unwrap();
 
-        let client_thread = thread::spawn(move || {
-            let transport = TcpTransport { addr, timeout: Some(time::Duration::from_secs(5)) };
-            let client = Client::with_transport(transport);
+        let server_thread = thread::spawn(move || {
+            let (mut stream, _) = server.accept().unwrap();
+            let req: serde_json::Value = serde_json::Deserializer::from_reader(&mut stream)
+                .into_iter()
+                .next()
+                .unwrap()
+                .unwrap();
+            assert_eq!(req["method"], "blockchain.headers.subscribe");
+
+            stream.write_all(br#"{"jsonrpc":"2.0","result":{"height":1},"id":1}"#).unwrap();
+            stream
+                .write_all(br#"{"jsonrpc":"2.0","method":"blockchain.headers.subscribe","params":[{"height":2}]}"#)
+                .unwrap();
+            stream.write_all(b"\n").unwrap();
+            stream
+                .write_all(br#"{"jsonrpc":"2.0","method":"blockchain.headers.subscribe","params":[{"height":3}]}"#)
+                .unwrap();
+        });
+
+        let transport =
+            TcpTransport { timeout: Some(time::Duration::from_secs(5)), ..TcpTransport::new(addr) };
+        let client = Client::with_transport(transport.clone());
+        let request = client.build_request("blockchain.headers.subscribe", None);
+        let notifications: Vec<_> =
+            transport.subscribe(request).unwrap().map(|n| n.unwrap()).collect();
+        server_thread.join().unwrap();
+
+        assert_eq!(notifications.len(), 2);
+        assert_eq!(notifications[0].method, "blockchain.headers.subscribe");
+        assert_eq!(notifications[0].params.as_ref().unwrap().get(), r#"[{"height":2}]"#);
+        assert_eq!(notifications[1].params.as_ref().unwrap().get(), r#"[{"height":3}]"#);
+    }
+
+    #[test]
+    fn drain_until() {
+        let addr: net::SocketAddr =
+            net::SocketAddrV4::new(net::Ipv4Addr::new(127, 0, 0, 1), 0).into();
+        let server = net::TcpListener::bind(addr).unwrap();
+        let addr = server.local_addr().unwrap();
+
+        let server_thread = thread::spawn(move || {
+            let (mut stream, _) = server.accept().unwrap();
+            let _: serde_json::Value = serde_json::Deserializer::from_reader(&mut stream)
+                .into_iter()
+                .next()
+                .unwrap()
+                .unwrap();
+
+            stream.write_all(br#"{"result":null,"id":7}"#).unwrap();
+            stream.write_all(br#"{"method":"first","params":[]}"#).unwrap();
+            stream.write_all(br#"{"result":null,"id":8}"#).unwrap();
+            stream.write_all(br#"{"method":"second","params":[]}"#).unwrap();
+        });
+
+        let transport =
+            TcpTransport { timeout: Some(time::Duration::from_secs(5)), ..TcpTransport::new(addr) };
+        let client = Client::with_transport(transport.clone());
+        let mut subscription =
+            transport.subscribe(client.build_request("subscribe", None)).unwrap();
+        subscription
+            .drain_until(&serde_json::Value::from(8), time::Duration::from_secs(5))
+            .unwrap();
+        server_thread.join().unwrap();
+
+        assert_eq!(subscription.next().unwrap().unwrap().method, "first");
+        assert_eq!(subscription.next().unwrap().unwrap().method, "second");
+        assert!(subscription.next().is_none());
+        // The connection is closed, so there is nothing left to drain.
+        assert!(subscription
+            .drain_until(&serde_json::Value::from(9), time::Duration::from_secs(5))
+            .is_err());
+    }
+
+    #[test]
+    fn drain_until_timeout() {
+        let addr: net::SocketAddr =
+            net::SocketAddrV4::new(net::Ipv4Addr::new(127, 0, 0, 1), 0).into();
+        let server = net::TcpListener::bind(addr).unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = server.accept().unwrap();
+            // Keep pushing notifications, but never the awaited response.
+            while stream.write_all(br#"{"method":"ping","params":[]}"#).is_ok() {
+                thread::sleep(time::Duration::from_millis(10));
+            }
+        });
+
+        // Without a transport timeout, only the one of `drain_until` applies.
+        let transport = TcpTransport::new(addr);
+        let client = Client::with_transport(transport.clone());
+        let mut subscription =
+            transport.subscribe(client.build_request("subscribe", None)).unwrap();
+        let start = time::Instant::now();
+        let res =
+            subscription.drain_until(&serde_json::Value::from(1), time::Duration::from_millis(200));
+        assert!(matches!(res, Err(Error::Timeout)));
+        assert!(start.elapsed() < time::Duration::from_secs(5));
+        assert_eq!(subscription.next().unwrap().unwrap().method, "ping");
+    }
 
-            client.send_request(dummy_req.clone()).unwrap()
+    #[test]
+    fn deadline() {
+        let addr: net::SocketAddr =
+            net::SocketAddrV4::new(net::Ipv4Addr::new(127, 0, 0, 1), 0).into();
+        let server = net::TcpListener::bind(addr).unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = server.accept().unwrap();
+            // Trickle out a response which never ends, each byte well within the timeout.
+            stream.write_all(br#"{"result":""#).unwrap();
+            while stream.write_all(b"a").is_ok() {
+                thread::sleep(time::Duration::from_millis(10));
+            }
         });
 
-        let (mut stream, _) = server.accept().unwrap();
-        stream.set_read_timeout(Some(time::Duration::from_secs(5))).unwrap();
-        let mut recv_req = vec![0; dummy_req_ser.len()];
-        let mut read = 0;
-        while read < dummy_req_ser.len() {
-            read += stream.read(&mut recv_req[read..]).unwrap();
+        let transport = TcpTransport {
+            timeout: Some(time::Duration::from_secs(5)),
+            ..TcpTransport::new(addr).deadline(time::Duration::from_millis(200))
+        };
+        let client = Client::with_transport(transport);
+        let start = time::Instant::now();
+        match client.send_request(client.build_request("test", None)) {
+            Err(crate::Error::Transport(e)) =>
+                assert!(matches!(e.downcast_ref(), Some(Error::Timeout))),
+            res => panic!("unexpected result {:?}", res),
         }
-        assert_eq!(recv_req, dummy_req_ser);
+        assert!(start.elapsed() < time::Duration::from_secs(5));
+    }
+
+    #[test]
+    fn newline_framing() {
+        let server = EchoServer::tcp(Protocol::Raw).unwrap();
+        let transport = TcpTransport {
+            timeout: Some(time::Duration::from_secs(5)),
+            ..TcpTransport::new(server.socket_addr().unwrap()).newline_framing(true)
+        };
+        let client = Client::with_transport(transport);
+        assert!(client.call::<Vec<bool>>("test", Some(&crate::arg([true]))).unwrap()[0]);
+    }
+
+    #[test]
+    fn check_connection() {
+        let server = EchoServer::tcp(Protocol::Raw).unwrap();
+        let client = Client::with_transport(TcpTransport::new(server.socket_addr().unwrap()));
+        client.check_connection().unwrap();
+
+        // Nobody is listening anymore.
+        drop(server);
+        assert!(client.check_connection().is_err());
+    }
+
+    #[cfg(feature = "bind_addr")]
+    #[test]
+    fn bind_addr() {
+        let addr: net::SocketAddr =
+            net::SocketAddrV4::new(net::Ipv4Addr::new(127, 0, 0, 1), 0).into();
+        let server = net::TcpListener::bind(addr).unwrap();
+        let addr = server.local_addr().unwrap();
+        // Find a free local port to connect from.
+        let bind_addr = net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
 
-        stream.write_all(&dummy_resp_ser).unwrap();
-        stream.flush().unwrap();
-        let recv_resp = client_thread.join().unwrap();
-        assert_eq!(serde_json::to_vec(&recv_resp).unwrap(), dummy_resp_ser);
+        let transport = TcpTransport::new(addr).bind_addr(bind_addr);
+        transport.check_connection().unwrap();
+        let (_, peer_addr) = server.accept().unwrap();
+        assert_eq!(peer_addr, bind_addr);
     }
 }
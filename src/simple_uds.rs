@@ -2,11 +2,12 @@
 
 //! This module implements a synchronous transport over a raw [`std::os::unix::net::UnixStream`].
 
+use std::io::Write;
 use std::os::unix::net::UnixStream;
 use std::{error, fmt, io, path, time};
 
 use crate::client::Transport;
-use crate::{Request, Response};
+use crate::{read_response, read_with_deadline, BatchEntry, Request, Response};
 
 /// Simple synchronous UDS transport.
 #[derive(Debug, Clone)]
@@ -15,30 +16,59 @@ pub struct UdsTransport {
     pub sockpath: path::PathBuf,
     /// The read and write timeout to use.
     pub timeout: Option<time::Duration>,
+    /// The time after which a request fails with [`Error::Timeout`], however fast each read is.
+    deadline: Option<time::Duration>,
+    /// Whether requests are terminated with a newline, and responses read up to one, for
+    /// servers which exchange newline-delimited JSON.
+    newline_framing: bool,
 }
 
 impl UdsTransport {
     /// Creates a new [`UdsTransport`] without timeouts to use.
     pub fn new<P: AsRef<path::Path>>(sockpath: P) -> UdsTransport {
-        UdsTransport { sockpath: sockpath.as_ref().to_path_buf(), timeout: None }
+        UdsTransport {
+            sockpath: sockpath.as_ref().to_path_buf(),
+            timeout: None,
+            deadline: None,
+            newline_framing: false,
+        }
+    }
+
+    /// Sets the time after which a request fails with [`Error::Timeout`], however fast each
+    /// read is, so that a server trickling out a response can't hold the request indefinitely.
+    /// No deadline is set by default.
+    pub fn deadline(mut self, deadline: time::Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Sets whether requests are terminated with a newline, and responses read up to one, for
+    /// servers which exchange newline-delimited JSON rather than bare JSON values. Defaults to
+    /// `false`.
+    pub fn newline_framing(mut self, newline_framing: bool) -> Self {
+        self.newline_framing = newline_framing;
+        self
     }
 
     fn request<R>(&self, req: impl serde::Serialize) -> Result<R, Error>
     where
         R: for<'a> serde::de::Deserialize<'a>,
     {
+        let deadline = self.deadline.map(|d| time::Instant::now() + d);
         let mut sock = UnixStream::connect(&self.sockpath)?;
         sock.set_read_timeout(self.timeout)?;
         sock.set_write_timeout(self.timeout)?;
 
         serde_json::to_writer(&mut sock, &req)?;
+        if self.newline_framing {
+            sock.write_all(b"\n")?;
+        }
 
         // NOTE: we don't check the id there, so it *must* be synchronous
-        let resp: R = serde_json::Deserializer::from_reader(&mut sock)
-            .into_iter()
-            .next()
-            .ok_or(Error::Timeout)??;
-        Ok(resp)
+        let framing = self.newline_framing;
+        read_with_deadline(&mut sock, self.timeout, deadline, Error::Timeout, |reader| {
+            read_response(reader, framing)?.ok_or(Error::Timeout)
+        })
     }
 }
 
@@ -51,9 +81,21 @@ impl Transport for UdsTransport {
         Ok(self.request(reqs)?)
     }
 
+    fn send_mixed_batch(
+        &self,
+        entries: &[BatchEntry],
+    ) -> Result<Vec<Response>, crate::error::Error> {
+        Ok(self.request(entries)?)
+    }
+
     fn fmt_target(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}", self.sockpath.to_string_lossy())
     }
+
+    fn check_connection(&self) -> Result<(), crate::error::Error> {
+        UnixStream::connect(&self.sockpath).map_err(Error::SocketError)?;
+        Ok(())
+    }
 }
 
 /// Error that can occur while using the UDS transport.
@@ -67,6 +109,13 @@ pub enum Error {
     Json(serde_json::Error),
 }
 
+impl Error {
+    /// Returns whether the request may succeed if sent again, see [`crate::Error::is_retryable`].
+    pub(crate) fn is_retryable(&self) -> bool {
+        matches!(*self, Error::SocketError(_) | Error::Timeout)
+    }
+}
+
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         use Error::*;
@@ -110,11 +159,10 @@ impl From<Error> for crate::error::Error {
 
 #[cfg(test)]
 mod tests {
-    use std::io::{Read, Write};
-    use std::os::unix::net::UnixListener;
-    use std::{fs, process, thread};
+    use std::{fs, process};
 
     use super::*;
+    use crate::testing::{EchoServer, Protocol};
     use crate::Client;
 
     // Test a dummy request / response over an UDS
@@ -124,49 +172,21 @@ mod tests {
         // Any leftover?
         fs::remove_file(&socket_path).unwrap_or(());
 
-        let server = UnixListener::bind(&socket_path).unwrap();
-        let dummy_req = Request {
-            method: "getinfo",
-            params: None,
-            id: serde_json::Value::Number(111.into()),
-            jsonrpc: Some("2.0"),
-        };
-        let dummy_req_ser = serde_json::to_vec(&dummy_req).unwrap();
-        let dummy_resp = Response {
-            result: None,
-            error: None,
-            id: serde_json::Value::Number(111.into()),
-            jsonrpc: Some("2.0".into()),
+        let server = EchoServer::unix(&socket_path, Protocol::Raw).unwrap();
+        let transport = UdsTransport {
+            timeout: Some(time::Duration::from_secs(5)),
+            ..UdsTransport::new(&socket_path)
         };
-        let dummy_resp_ser = serde_json::to_vec(&dummy_resp).unwrap();
-
-        let cli_socket_path = socket_path.clone();
-        let client_thread = thread::spawn(move || {
-            let transport = UdsTransport {
-                sockpath: cli_socket_path,
-                timeout: Some(time::Duration::from_secs(5)),
-            };
-            let client = Client::with_transport(transport);
-
-            client.send_request(dummy_req.clone()).unwrap()
-        });
-
-        let (mut stream, _) = server.accept().unwrap();
-        stream.set_read_timeout(Some(time::Duration::from_secs(5))).unwrap();
-        let mut recv_req = vec![0; dummy_req_ser.len()];
-        let mut read = 0;
-        while read < dummy_req_ser.len() {
-            read += stream.read(&mut recv_req[read..]).unwrap();
-        }
-        assert_eq!(recv_req, dummy_req_ser);
+        let client = Client::with_transport(transport);
 
-        stream.write_all(&dummy_resp_ser).unwrap();
-        stream.flush().unwrap();
-        let recv_resp = client_thread.join().unwrap();
-        assert_eq!(serde_json::to_vec(&recv_resp).unwrap(), dummy_resp_ser);
+        let params = crate::arg(["getinfo"]);
+        let request = client.build_request("getinfo", Some(&params));
+        let response = client.send_request(request.clone()).unwrap();
+        assert_eq!(response.id, request.id);
+        assert_eq!(response.result.unwrap().get(), r#"["getinfo"]"#);
 
-        // Clean up
+        // The socket is removed on shutdown.
         drop(server);
-        fs::remove_file(&socket_path).unwrap();
+        assert!(!socket_path.exists());
     }
 }
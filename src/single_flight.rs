@@ -0,0 +1,194 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! This module implements a [`crate::client::Transport`] which merges identical requests made
+//! concurrently into a single request to another transport.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Condvar, Mutex};
+
+use crate::client::Transport;
+use crate::{BatchEntry, Error, Request, Response};
+
+/// A transport which sends only one of several identical requests in flight at the same time.
+///
+/// Requests are identical if they have the same method and serialized parameters. While a
+/// request is in flight, identical requests wait for it to complete and share its response,
+/// with their own ID. If it fails, each of them is sent on its own instead. Unlike
+/// [`crate::caching::CachingTransport`], nothing is kept once the request completes. Batches
+/// are always forwarded to the inner transport.
+pub struct SingleFlightTransport<T> {
+    /// The transport to forward requests to.
+    inner: T,
+    /// The requests in flight, keyed by their serialized method and parameters.
+    in_flight: Mutex<HashMap<String, Arc<Flight>>>,
+}
+
+/// A request in flight, which identical requests wait on.
+#[derive(Default)]
+struct Flight {
+    /// Set once the request completes, to the response if it succeeded.
+    outcome: Mutex<Option<Option<Response>>>,
+    done: Condvar,
+}
+
+/// Completes a request in flight when dropped, so that identical requests waiting on it don't
+/// block forever if the inner transport panics.
+struct Completion<'a> {
+    in_flight: &'a Mutex<HashMap<String, Arc<Flight>>>,
+    key: String,
+    flight: Arc<Flight>,
+    /// The response to share with the waiting requests, if the request succeeded.
+    response: Option<Response>,
+}
+
+impl Drop for Completion<'_> {
+    fn drop(&mut self) {
+        // No part of this codebase should panic, so unwrapping a mutex lock is fine
+        self.in_flight.lock().expect("poisoned mutex").remove(&self.key);
+        *self.flight.outcome.lock().expect("poisoned mutex") = Some(self.response.take());
+        self.flight.done.notify_all();
+    }
+}
+
+impl<T: Transport> SingleFlightTransport<T> {
+    /// Creates a new [`SingleFlightTransport`].
+    pub fn new(inner: T) -> SingleFlightTransport<T> {
+        SingleFlightTransport { inner, in_flight: Mutex::new(HashMap::new()) }
+    }
+
+    /// Returns the inner transport.
+    pub fn into_inner(self) -> T { self.inner }
+}
+
+impl Flight {
+    /// Waits for the request to complete and returns its response, if it succeeded.
+    fn wait(&self) -> Option<Response> {
+        // No part of this codebase should panic, so unwrapping a mutex lock is fine
+        let mut outcome = self.outcome.lock().expect("poisoned mutex");
+        loop {
+            if let Some(ref response) = *outcome {
+                return response.clone();
+            }
+            outcome = self.done.wait(outcome).expect("poisoned mutex");
+        }
+    }
+}
+
+impl<T: Transport> Transport for SingleFlightTransport<T> {
+    fn send_request(&self, req: Request) -> Result<Response, Error> {
+        let key = serde_json::to_string(&(req.method, req.params))?;
+        // No part of this codebase should panic, so unwrapping a mutex lock is fine
+        let mut in_flight = self.in_flight.lock().expect("poisoned mutex");
+        if let Some(flight) = in_flight.get(&key).cloned() {
+            drop(in_flight);
+            return match flight.wait() {
+                Some(mut response) => {
+                    // Answer with the ID of this request, so that the client accepts the response.
+                    response.id = req.id;
+                    Ok(response)
+                }
+                None => self.inner.send_request(req),
+            };
+        }
+        let flight = Arc::new(Flight::default());
+        in_flight.insert(key.clone(), flight.clone());
+        drop(in_flight);
+
+        let mut completion = Completion { in_flight: &self.in_flight, key, flight, response: None };
+        let result = self.inner.send_request(req);
+        completion.response = result.as_ref().ok().cloned();
+        result
+    }
+
+    fn send_batch(&self, reqs: &[Request]) -> Result<Vec<Response>, Error> {
+        self.inner.send_batch(reqs)
+    }
+
+    fn send_mixed_batch(&self, entries: &[BatchEntry]) -> Result<Vec<Response>, Error> {
+        self.inner.send_mixed_batch(entries)
+    }
+
+    fn fmt_target(&self, f: &mut fmt::Formatter) -> fmt::Result { self.inner.fmt_target(f) }
+
+    fn supports_batch(&self) -> bool { self.inner.supports_batch() }
+
+    fn check_connection(&self) -> Result<(), Error> { self.inner.check_connection() }
+
+    fn shutdown(&self) -> Result<(), Error> { self.inner.shutdown() }
+}
+
+impl<T: fmt::Debug> fmt::Debug for SingleFlightTransport<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SingleFlightTransport").field("inner", &self.inner).finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Barrier;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::testing::{response, FnTransport};
+    use crate::Client;
+
+    /// A slow transport which counts its requests and answers with the count.
+    fn slow_transport(count: Arc<AtomicUsize>) -> FnTransport {
+        FnTransport::new(move |req| {
+            let n = count.fetch_add(1, Ordering::SeqCst) + 1;
+            thread::sleep(Duration::from_millis(200));
+            Ok(response(req.id, &n.to_string()))
+        })
+    }
+
+    #[test]
+    fn single_flight() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let tp = SingleFlightTransport::new(slow_transport(count.clone()));
+        let client = Arc::new(Client::with_transport(tp));
+        let barrier = Arc::new(Barrier::new(4));
+
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let client = client.clone();
+                let barrier = barrier.clone();
+                thread::spawn(move || {
+                    barrier.wait();
+                    client.call::<usize>("getblockcount", None).unwrap()
+                })
+            })
+            .collect();
+        for thread in threads {
+            assert_eq!(thread.join().unwrap(), 1);
+        }
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+
+        // Once completed, the request is sent again.
+        assert_eq!(client.call::<usize>("getblockcount", None).unwrap(), 2);
+    }
+
+    #[test]
+    fn panicking_request() {
+        // A slow transport whose first request panics.
+        let slow = slow_transport(Default::default());
+        let panicking = FnTransport::new(move |req| {
+            let response = slow.send_request(req)?;
+            assert_ne!(response.result.as_ref().unwrap().get(), "1", "first request");
+            Ok(response)
+        });
+
+        let tp = SingleFlightTransport::new(panicking);
+        let client = Arc::new(Client::with_transport(tp));
+        let first = {
+            let client = client.clone();
+            thread::spawn(move || client.call::<usize>("getblockcount", None))
+        };
+        thread::sleep(Duration::from_millis(50));
+        // This waits on the first request, then is sent on its own once that one panics.
+        assert_eq!(client.call::<usize>("getblockcount", None).unwrap(), 2);
+        assert!(first.join().is_err());
+    }
+}
@@ -0,0 +1,469 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! This module implements a minimal JSON-RPC server for testing transports and clients, which
+//! answers every request with its own parameters, and a transport answering requests with a
+//! function, for testing clients without a server.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{Ipv4Addr, Shutdown, SocketAddr, TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::{fmt, io};
+
+use serde_json::value::RawValue;
+use serde_json::Value;
+
+use crate::client::Transport;
+use crate::{BatchEntry, Error, Request, Response};
+
+/// The maximum number of header lines of a HTTP request accepted by the [`EchoServer`].
+const MAX_HEADERS: usize = 100;
+
+/// How an [`EchoServer`] exchanges messages with its clients.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Protocol {
+    /// HTTP/1.1 with keep-alive, as spoken by the HTTP transports.
+    Http,
+    /// JSON values written back to back on the socket, as spoken by the TCP and UDS transports.
+    ///
+    /// Each response is followed by a newline, so that transports using newline framing can be
+    /// tested as well.
+    Raw,
+}
+
+/// The address an [`EchoServer`] listens on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Address {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+/// A JSON-RPC server running in a background thread, answering every request with a result equal
+/// to its parameters.
+///
+/// Batches are answered with a batch of responses, and notifications are not answered. The server
+/// is shut down, closing all its connections, when it is dropped.
+///
+/// # Examples
+///
+/// ```
+/// # #[cfg(feature = "simple_tcp")] {
+/// use jsonrpc::simple_tcp::TcpTransport;
+/// use jsonrpc::testing::{EchoServer, Protocol};
+/// use jsonrpc::{arg, Client};
+///
+/// let server = EchoServer::tcp(Protocol::Raw).unwrap();
+/// let client = Client::with_transport(TcpTransport::new(server.socket_addr().unwrap()));
+/// let params = arg(["hello"]);
+/// let echo = client.call::<Vec<String>>("echo", Some(&params)).unwrap();
+/// assert_eq!(echo, ["hello"]);
+/// # }
+/// ```
+pub struct EchoServer {
+    addr: Address,
+    /// Set when the server is dropped, to stop accepting connections.
+    stop: Arc<AtomicBool>,
+    /// The thread accepting connections.
+    acceptor: Option<JoinHandle<()>>,
+    /// The open connections, closed when the server is dropped.
+    connections: Arc<Mutex<Vec<Connection>>>,
+}
+
+/// A connection served by its own thread.
+struct Connection {
+    socket: Box<dyn Socket>,
+    handle: JoinHandle<()>,
+}
+
+/// The operations the [`EchoServer`] needs on its sockets, for both TCP and Unix sockets.
+trait Socket: Read + Write + Send + 'static {
+    fn try_clone_box(&self) -> io::Result<Box<dyn Socket>>;
+    fn shutdown(&self) -> io::Result<()>;
+}
+
+impl Socket for TcpStream {
+    fn try_clone_box(&self) -> io::Result<Box<dyn Socket>> { Ok(Box::new(self.try_clone()?)) }
+    fn shutdown(&self) -> io::Result<()> { TcpStream::shutdown(self, Shutdown::Both) }
+}
+
+#[cfg(unix)]
+impl Socket for UnixStream {
+    fn try_clone_box(&self) -> io::Result<Box<dyn Socket>> { Ok(Box::new(self.try_clone()?)) }
+    fn shutdown(&self) -> io::Result<()> { UnixStream::shutdown(self, Shutdown::Both) }
+}
+
+impl EchoServer {
+    /// Starts a server listening on a free TCP port of the loopback interface.
+    pub fn tcp(protocol: Protocol) -> io::Result<EchoServer> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+        let addr = Address::Tcp(listener.local_addr()?);
+        Ok(EchoServer::start(addr, protocol, move || Ok(listener.accept()?.0)))
+    }
+
+    /// Starts a server listening on a Unix socket at `path`, which is removed on shutdown.
+    #[cfg(unix)]
+    pub fn unix<P: Into<PathBuf>>(path: P, protocol: Protocol) -> io::Result<EchoServer> {
+        let path = path.into();
+        let listener = UnixListener::bind(&path)?;
+        let addr = Address::Unix(path);
+        Ok(EchoServer::start(addr, protocol, move || Ok(listener.accept()?.0)))
+    }
+
+    fn start<S, A>(addr: Address, protocol: Protocol, mut accept: A) -> EchoServer
+    where
+        S: Socket,
+        A: FnMut() -> io::Result<S> + Send + 'static,
+    {
+        let stop = Arc::new(AtomicBool::new(false));
+        let connections = Arc::new(Mutex::new(Vec::<Connection>::new()));
+
+        let (acceptor_stop, acceptor_connections) = (stop.clone(), connections.clone());
+        let acceptor = thread::spawn(move || {
+            while let Ok(stream) = accept() {
+                if acceptor_stop.load(Ordering::SeqCst) {
+                    break;
+                }
+                let socket = match stream.try_clone_box() {
+                    Ok(socket) => socket,
+                    Err(_) => continue,
+                };
+                let handle = thread::spawn(move || {
+                    // Errors only mean that the client went away.
+                    let _ = match protocol {
+                        Protocol::Http => serve_http(stream),
+                        Protocol::Raw => serve_raw(stream),
+                    };
+                });
+                // No part of this codebase should panic, so unwrapping a mutex lock is fine
+                let mut connections = acceptor_connections.lock().expect("poisoned mutex");
+                connections.retain(|conn: &Connection| !conn.handle.is_finished());
+                connections.push(Connection { socket, handle });
+            }
+        });
+
+        EchoServer { addr, stop, acceptor: Some(acceptor), connections }
+    }
+
+    /// Returns the address of the server if it listens on TCP.
+    pub fn socket_addr(&self) -> Option<SocketAddr> {
+        match self.addr {
+            Address::Tcp(addr) => Some(addr),
+            #[cfg(unix)]
+            Address::Unix(_) => None,
+        }
+    }
+
+    /// Returns the path of the socket of the server if it listens on a Unix socket.
+    pub fn socket_path(&self) -> Option<&Path> {
+        match self.addr {
+            Address::Tcp(_) => None,
+            #[cfg(unix)]
+            Address::Unix(ref path) => Some(path),
+        }
+    }
+}
+
+impl Drop for EchoServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        // Wake up the acceptor, which stops before serving this connection.
+        let _ = match self.addr {
+            Address::Tcp(addr) => TcpStream::connect(addr).map(drop),
+            #[cfg(unix)]
+            Address::Unix(ref path) => UnixStream::connect(path).map(drop),
+        };
+        if let Some(acceptor) = self.acceptor.take() {
+            let _ = acceptor.join();
+        }
+
+        // No part of this codebase should panic, so unwrapping a mutex lock is fine
+        let connections = std::mem::take(&mut *self.connections.lock().expect("poisoned mutex"));
+        for conn in connections {
+            let _ = conn.socket.shutdown();
+            let _ = conn.handle.join();
+        }
+
+        #[cfg(unix)]
+        if let Address::Unix(ref path) = self.addr {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+impl fmt::Debug for EchoServer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("EchoServer").field("addr", &self.addr).finish_non_exhaustive()
+    }
+}
+
+/// Returns the response to a request or batch, if it contains any request which isn't a
+/// notification.
+fn echo(request: &Value) -> Option<Value> {
+    match *request {
+        Value::Array(ref batch) => {
+            let responses: Vec<_> = batch.iter().filter_map(echo_one).collect();
+            if responses.is_empty() {
+                None
+            } else {
+                Some(Value::Array(responses))
+            }
+        }
+        _ => echo_one(request),
+    }
+}
+
+/// Returns the response to a single request, unless it is a notification.
+fn echo_one(request: &Value) -> Option<Value> {
+    let id = request.get("id")?;
+    Some(serde_json::json!({
+        "jsonrpc": "2.0",
+        "result": request.get("params").cloned().unwrap_or(Value::Null),
+        "error": null,
+        "id": id,
+    }))
+}
+
+/// Serves requests written back to back on `stream` until it is closed.
+fn serve_raw<S: Socket>(mut stream: S) -> io::Result<()> {
+    let reader = BufReader::new(stream.try_clone_box()?);
+    for request in serde_json::Deserializer::from_reader(reader).into_iter::<Value>() {
+        // Stop at the end of the stream, or on garbage.
+        let request = match request {
+            Ok(request) => request,
+            Err(_) => return Ok(()),
+        };
+        if let Some(response) = echo(&request) {
+            let mut bytes = serde_json::to_vec(&response)?;
+            bytes.push(b'\n');
+            stream.write_all(&bytes)?;
+        }
+    }
+    Ok(())
+}
+
+/// Serves HTTP requests on `stream` until it is closed, or a request asks for it to be.
+fn serve_http<S: Socket>(mut stream: S) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone_box()?);
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+
+        let (mut content_length, mut chunked, mut close, mut expect_continue) =
+            (0, false, false, false);
+        for _ in 0..MAX_HEADERS {
+            line.clear();
+            reader.read_line(&mut line)?;
+            let line = line.trim();
+            if line.is_empty() {
+                break;
+            }
+            let (name, value) = line.split_once(':').unwrap_or((line, ""));
+            let (name, value) = (name.trim(), value.trim());
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.parse().map_err(|_| io::ErrorKind::InvalidData)?;
+            } else if name.eq_ignore_ascii_case("transfer-encoding") {
+                chunked = value.eq_ignore_ascii_case("chunked");
+            } else if name.eq_ignore_ascii_case("connection") {
+                close = value.eq_ignore_ascii_case("close");
+            } else if name.eq_ignore_ascii_case("expect") {
+                expect_continue = value.eq_ignore_ascii_case("100-continue");
+            }
+        }
+
+        if expect_continue {
+            stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+        }
+        let body = if chunked {
+            read_chunked(&mut reader)?
+        } else {
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body)?;
+            body
+        };
+
+        let response = match serde_json::from_slice(&body) {
+            Ok(request) => match echo(&request) {
+                Some(response) => {
+                    let body = serde_json::to_string(&response)?;
+                    format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\
+                         Content-Length: {}\r\n\r\n{}",
+                        body.len(),
+                        body
+                    )
+                }
+                None => "HTTP/1.1 204 No Content\r\nContent-Length: 0\r\n\r\n".to_owned(),
+            },
+            Err(_) => "HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n".to_owned(),
+        };
+        stream.write_all(response.as_bytes())?;
+        if close {
+            return Ok(());
+        }
+    }
+}
+
+/// Reads a body sent with `Transfer-Encoding: chunked`.
+fn read_chunked<R: BufRead>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let mut body = vec![];
+    let mut line = String::new();
+    loop {
+        line.clear();
+        reader.read_line(&mut line)?;
+        let size = line.trim().split(';').next().unwrap_or("");
+        let size = usize::from_str_radix(size, 16).map_err(|_| io::ErrorKind::InvalidData)?;
+        if size == 0 {
+            break;
+        }
+        let start = body.len();
+        body.resize(start + size, 0);
+        reader.read_exact(&mut body[start..])?;
+        // The line break ending the chunk.
+        reader.read_line(&mut line)?;
+    }
+    // Skip the trailer fields, up to the empty line ending the request.
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            return Ok(body);
+        }
+    }
+}
+
+/// The function answering the requests of a [`FnTransport`].
+type RequestFn = Box<dyn Fn(Request) -> Result<Response, Error> + Send + Sync>;
+
+/// The function answering the batches of a [`FnTransport`].
+type BatchFn = Box<dyn Fn(&[Request]) -> Result<Vec<Response>, Error> + Send + Sync>;
+
+/// A transport answering each request with a function, for testing clients and transports which
+/// wrap other transports.
+///
+/// Batches are answered request by request with the same function, unless a function of their
+/// own is set with [`FnTransport::batch`]. The notifications of mixed batches are dropped.
+///
+/// # Examples
+///
+/// ```
+/// use jsonrpc::testing::{response, FnTransport};
+/// use jsonrpc::Client;
+///
+/// let transport = FnTransport::new(|req| Ok(response(req.id, "42")));
+/// let client = Client::with_transport(transport);
+/// assert_eq!(client.call::<u64>("getblockcount", None).unwrap(), 42);
+/// ```
+pub struct FnTransport {
+    request: RequestFn,
+    batch: Option<BatchFn>,
+    supports_batch: bool,
+}
+
+impl FnTransport {
+    /// Creates a new [`FnTransport`] answering requests with `request`.
+    pub fn new<F>(request: F) -> FnTransport
+    where
+        F: Fn(Request) -> Result<Response, Error> + Send + Sync + 'static,
+    {
+        FnTransport { request: Box::new(request), batch: None, supports_batch: true }
+    }
+
+    /// Answers batches with `batch` rather than request by request.
+    pub fn batch<F>(mut self, batch: F) -> FnTransport
+    where
+        F: Fn(&[Request]) -> Result<Vec<Response>, Error> + Send + Sync + 'static,
+    {
+        self.batch = Some(Box::new(batch));
+        self
+    }
+
+    /// Sets whether the transport claims to support batches, which it does by default.
+    pub fn batch_support(mut self, supported: bool) -> FnTransport {
+        self.supports_batch = supported;
+        self
+    }
+}
+
+impl Transport for FnTransport {
+    fn send_request(&self, req: Request) -> Result<Response, Error> { (self.request)(req) }
+
+    fn send_batch(&self, reqs: &[Request]) -> Result<Vec<Response>, Error> {
+        match self.batch {
+            Some(ref batch) => batch(reqs),
+            None => reqs.iter().map(|req| (self.request)(req.clone())).collect(),
+        }
+    }
+
+    fn send_mixed_batch(&self, entries: &[BatchEntry]) -> Result<Vec<Response>, Error> {
+        let reqs: Vec<_> = entries
+            .iter()
+            .filter_map(|entry| match *entry {
+                BatchEntry::Request(ref req) => Some(req.clone()),
+                BatchEntry::Notification(_) => None,
+            })
+            .collect();
+        self.send_batch(&reqs)
+    }
+
+    fn fmt_target(&self, f: &mut fmt::Formatter) -> fmt::Result { f.write_str("fn") }
+
+    fn supports_batch(&self) -> bool { self.supports_batch }
+}
+
+impl fmt::Debug for FnTransport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FnTransport")
+            .field("supports_batch", &self.supports_batch)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Returns a successful JSON-RPC 2.0 response with the given ID and raw JSON result.
+///
+/// # Panics
+///
+/// If `result` isn't valid JSON.
+pub fn response(id: Value, result: &str) -> Response {
+    Response {
+        result: Some(RawValue::from_string(result.to_owned()).expect("valid JSON result")),
+        error: None,
+        id,
+        jsonrpc: Some(String::from("2.0")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn echo_batch() {
+        let batch = serde_json::json!([
+            {"method": "a", "params": [1], "id": 1},
+            {"method": "b", "params": null},
+            {"method": "c", "id": "x"},
+        ]);
+        let expected = serde_json::json!([
+            {"jsonrpc": "2.0", "result": [1], "error": null, "id": 1},
+            {"jsonrpc": "2.0", "result": null, "error": null, "id": "x"},
+        ]);
+        assert_eq!(echo(&batch), Some(expected));
+        assert_eq!(echo(&serde_json::json!({"method": "b"})), None);
+    }
+
+    #[test]
+    fn shutdown() {
+        let server = EchoServer::tcp(Protocol::Http).unwrap();
+        let addr = server.socket_addr().unwrap();
+        // An idle connection doesn't keep the server from shutting down.
+        let _conn = TcpStream::connect(addr).unwrap();
+        drop(server);
+        assert!(TcpStream::connect(addr).is_err());
+    }
+}
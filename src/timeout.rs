@@ -0,0 +1,182 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! This module implements a [`crate::client::Transport`] which bounds the time requests sent
+//! through another transport may take, whether or not that transport supports timeouts.
+
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::time::Duration;
+use std::{fmt, thread};
+
+use serde_json::value::RawValue;
+
+use crate::client::Transport;
+use crate::{BatchEntry, Error, Notification, Request, Response};
+
+/// A transport which fails requests with [`Error::Timeout`] if the inner transport doesn't
+/// complete them in time.
+///
+/// Each request, or batch, is sent by the inner transport on a thread of its own, which the
+/// caller waits on for at most the timeout. On a timeout, the thread is abandoned rather than
+/// stopped: it runs until the inner transport returns, holding on to its resources, e.g. a
+/// connection, and its response is dropped. Prefer the timeouts of the transport itself where it
+/// has them, and use this for transports which don't.
+pub struct TimeoutTransport<T> {
+    /// The transport to send requests with.
+    inner: Arc<T>,
+    /// How long a request may take.
+    timeout: Duration,
+}
+
+/// A copy of a [`Request`] which can be moved to another thread.
+struct OwnedRequest {
+    method: String,
+    params: Option<Box<RawValue>>,
+    id: serde_json::Value,
+    jsonrpc: Option<String>,
+}
+
+impl OwnedRequest {
+    fn new(req: &Request) -> OwnedRequest {
+        OwnedRequest {
+            method: req.method.to_owned(),
+            params: req.params.map(ToOwned::to_owned),
+            id: req.id.clone(),
+            jsonrpc: req.jsonrpc.map(ToOwned::to_owned),
+        }
+    }
+
+    fn as_request(&self) -> Request<'_> {
+        Request {
+            method: &self.method,
+            params: self.params.as_deref(),
+            id: self.id.clone(),
+            jsonrpc: self.jsonrpc.as_deref(),
+        }
+    }
+}
+
+/// A copy of a [`BatchEntry`] which can be moved to another thread.
+enum OwnedEntry {
+    Request(OwnedRequest),
+    Notification(Notification),
+}
+
+impl<T: Transport> TimeoutTransport<T> {
+    /// Creates a new [`TimeoutTransport`] failing requests which take longer than `timeout`.
+    pub fn new(inner: T, timeout: Duration) -> TimeoutTransport<T> {
+        TimeoutTransport { inner: Arc::new(inner), timeout }
+    }
+
+    /// Runs `send` with the inner transport on a new thread, waiting at most the timeout for it
+    /// to complete.
+    fn run<R, F>(&self, send: F) -> Result<R, Error>
+    where
+        R: Send + 'static,
+        F: FnOnce(&T) -> Result<R, Error> + Send + 'static,
+    {
+        let (tx, rx) = mpsc::channel();
+        let inner = self.inner.clone();
+        thread::Builder::new()
+            .name("jsonrpc-timeout".to_owned())
+            .spawn(move || {
+                // The caller is gone if it timed out, in which case the result is dropped.
+                let _ = tx.send(send(&inner));
+            })
+            .map_err(|e| Error::Transport(Box::new(e)))?;
+
+        match rx.recv_timeout(self.timeout) {
+            Ok(res) => res,
+            Err(RecvTimeoutError::Timeout) => Err(Error::Timeout),
+            Err(RecvTimeoutError::Disconnected) =>
+                Err(Error::Transport("transport panicked while sending the request".into())),
+        }
+    }
+}
+
+impl<T: Transport> Transport for TimeoutTransport<T> {
+    fn send_request(&self, req: Request) -> Result<Response, Error> {
+        let req = OwnedRequest::new(&req);
+        self.run(move |inner| inner.send_request(req.as_request()))
+    }
+
+    fn send_batch(&self, reqs: &[Request]) -> Result<Vec<Response>, Error> {
+        let reqs: Vec<_> = reqs.iter().map(OwnedRequest::new).collect();
+        self.run(move |inner| {
+            let reqs: Vec<_> = reqs.iter().map(OwnedRequest::as_request).collect();
+            inner.send_batch(&reqs)
+        })
+    }
+
+    fn send_mixed_batch(&self, entries: &[BatchEntry]) -> Result<Vec<Response>, Error> {
+        let entries: Vec<_> = entries
+            .iter()
+            .map(|entry| match *entry {
+                BatchEntry::Request(ref req) => OwnedEntry::Request(OwnedRequest::new(req)),
+                BatchEntry::Notification(ref n) => OwnedEntry::Notification(n.clone()),
+            })
+            .collect();
+        self.run(move |inner| {
+            let entries: Vec<_> = entries
+                .iter()
+                .map(|entry| match *entry {
+                    OwnedEntry::Request(ref req) => BatchEntry::Request(req.as_request()),
+                    OwnedEntry::Notification(ref n) => BatchEntry::Notification(n.clone()),
+                })
+                .collect();
+            inner.send_mixed_batch(&entries)
+        })
+    }
+
+    fn fmt_target(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.inner.fmt_target(f)?;
+        write!(f, " (timeout: {:?})", self.timeout)
+    }
+
+    fn supports_batch(&self) -> bool { self.inner.supports_batch() }
+
+    fn check_connection(&self) -> Result<(), Error> { self.run(|inner| inner.check_connection()) }
+
+    fn shutdown(&self) -> Result<(), Error> { self.inner.shutdown() }
+}
+
+impl<T: fmt::Debug> fmt::Debug for TimeoutTransport<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TimeoutTransport")
+            .field("inner", &self.inner)
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::testing::{response, FnTransport};
+    use crate::Client;
+
+    #[test]
+    fn timeout() {
+        // Takes a second to answer the method `slow`.
+        let slow = FnTransport::new(|req| {
+            if req.method == "slow" {
+                thread::sleep(Duration::from_secs(1));
+            }
+            Ok(response(req.id, "true"))
+        });
+        let client = Client::with_timeout(slow, Duration::from_millis(100));
+        assert!(client.call::<bool>("fast", None).unwrap());
+
+        let start = Instant::now();
+        assert!(matches!(client.call::<bool>("slow", None), Err(Error::Timeout)));
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        let params = crate::arg([1]);
+        let fast = client.build_request("fast", Some(&params));
+        assert_eq!(client.send_batch(std::slice::from_ref(&fast)).unwrap().len(), 1);
+        let slow = client.build_request("slow", None);
+        assert!(matches!(client.send_batch(&[fast, slow]), Err(Error::Timeout)));
+    }
+}